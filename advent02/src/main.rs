@@ -1,102 +1,198 @@
+use std::collections::VecDeque;
 use std::fs::File;
+use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::str::FromStr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
 
 use structopt::StructOpt;
 
+use intcode::error::IntcodeError;
+use intcode::machine::Machine;
+use intcode::opcode::parse_program;
+use intcode::report::{OutputFormat, Report};
+use intcode::snapshot::Snapshot;
+use intcode::search::{combinations, patch, seek, seek_parallel, Goal, Patch, Vary};
+use intcode::trace::{open_tracer, TraceFormat, Tracer};
+use intcode::watchdog::{parse_seconds, Limits};
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "advent02", about = "Process Intcode.")]
 struct Opt {
     /// Input file containing a newline-separated list of module masses
     #[structopt(name = "FILE")]
     file_name: String,
-}
 
-fn tokenise(input: &str) -> Vec<u32> {
-    let values: Vec<&str> = input.split(",").collect();
-    let int_values: Vec<u32> = values.iter().map(|&value|
-        u32::from_str(&value.replace("\n", "")).unwrap()
-    ).collect();
+    /// Trace each instruction executed as off, text or json
+    #[structopt(long = "trace", default_value = "off")]
+    trace: TraceFormat,
 
-    return int_values;
-}
+    /// Write the trace to this file instead of stderr
+    #[structopt(long = "trace-file", parse(from_os_str))]
+    trace_file: Option<PathBuf>,
 
-fn process(mut input: Vec<u32>) -> Vec<u32> {
-    let mut idx = 0;
+    /// Give up on a noun and verb after this many instructions
+    #[structopt(long = "max-steps", default_value = "1000000")]
+    max_steps: u64,
 
-    loop {
-        print!("{}:\t", idx);
+    /// Give up on a noun and verb after this many seconds
+    #[structopt(long = "timeout", parse(try_from_str = "parse_seconds"))]
+    timeout: Option<Duration>,
 
-        match input[idx] {
-            1 => {
-                let lhs_idx = input[idx + 1];
-                let rhs_idx = input[idx + 2];
-                let out_idx = input[idx + 3];
+    /// Give up on a noun and verb as soon as the program is stuck in a loop
+    #[structopt(long = "detect-loops")]
+    detect_loops: bool,
 
-                let lhs = input[lhs_idx as usize];
-                let rhs = input[rhs_idx as usize];
+    /// Print the noun, verb and answer as plain, csv or json (with the final memory and
+    /// cycle count) instead of a sentence
+    #[structopt(long = "output-format")]
+    output_format: Option<OutputFormat>,
 
-                println!("ADD {}, {}, {} # ({}, {})", lhs_idx, rhs_idx, out_idx, lhs, rhs);
+    /// Set ADDR=VALUE before running, may be given more than once. Without --vary the
+    /// program is run once and the value at --target-address printed
+    #[structopt(long = "set", raw(number_of_values = "1"))]
+    set: Vec<Patch>,
 
-                input[out_idx as usize] = lhs + rhs;
-
-                idx += 4;
-            },
-            2 => {
-                let lhs_idx = input[idx + 1];
-                let rhs_idx = input[idx + 2];
-                let out_idx = input[idx + 3];
+    /// Try every value of ADDR=START..END, may be given more than once to search every
+    /// combination. Defaults to the noun and verb, 1=0..100 and 2=0..100
+    #[structopt(long = "vary", raw(number_of_values = "1"))]
+    vary: Vec<Vary>,
 
-                let lhs = input[lhs_idx as usize];
-                let rhs = input[rhs_idx as usize];
+    /// The value the search should leave at --target-address
+    #[structopt(long = "target", default_value = "19690720")]
+    target: i64,
 
-                println!("MUL {}, {}, {} # ({}, {})", lhs_idx, rhs_idx, out_idx, lhs, rhs);
+    /// The address holding the program's result
+    #[structopt(long = "target-address", default_value = "0")]
+    target_address: usize,
 
-                input[out_idx as usize] = lhs * rhs;
+    /// Report every combination that reaches the target rather than the first
+    #[structopt(long = "all")]
+    all: bool,
 
-                idx += 4;
-            },
-            99 => {
-                println!("HALT");
-                break;
-            },
-            _ => idx += 1,
-        }
-    }
+    /// Search on this many threads, defaulting to one per CPU. Tracing always uses one
+    #[structopt(long = "threads")]
+    threads: Option<usize>,
 
-    return input;
+    /// Show how many combinations have been tried on stderr while searching, unless tracing
+    #[structopt(long = "progress")]
+    progress: bool,
 }
 
-fn value_search(mut values: Vec<u32>, target: u32) -> Result<(u32, u32), ()> {
-    for noun in 0..100 {
-        for verb in 0..100 {
-            values[1] = noun;
-            values[2] = verb;
+fn process<T: Tracer>(start: &Snapshot, tracer: T, limits: &Limits) -> Result<Machine<VecDeque<i64>, Vec<i64>, T>, IntcodeError> {
+    let mut machine = Machine::from_snapshot(start).with_tracer(tracer);
 
-            let result = process(values.to_vec());
+    limits.apply(&mut machine);
 
-            if result[0] == target {
-                return Ok((noun, verb));
-            }
-        }
+    machine.run_until_blocked()?;
+
+    Ok(machine)
+}
+
+// Keep a count of combinations tried on one line of stderr until the search is done
+fn show_progress(tried: &AtomicU64, total: u64, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        eprint!("\rtried {} of {}", tried.load(Ordering::Relaxed), total);
+        thread::sleep(Duration::from_millis(100));
     }
 
-    Err(())
+    eprintln!("\rtried {} of {}", tried.load(Ordering::Relaxed), total);
 }
 
 fn main() -> std::io::Result<()> {
     let opt = Opt::from_args();
-    let file = File::open(opt.file_name)?;
+    let file = File::open(&opt.file_name)?;
     let mut reader = BufReader::new(file);
 
     let mut first_line = String::new();
     reader.read_line(&mut first_line)?;
 
-    let values = tokenise(&first_line);
-    let (noun, verb) = value_search(values, 19690720).unwrap();
+    let values = parse_program(&first_line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut tracer = open_tracer(opt.trace, opt.trace_file.as_deref())?;
+    let limits = Limits {
+        steps: Some(opt.max_steps),
+        timeout: opt.timeout,
+        detect_loops: opt.detect_loops,
+    };
+
+    let mut machine = Machine::new(values);
 
-    println!("noun = {}, verb = {}, 100 * {} + {} = {}", noun, verb, noun, verb, 100 * noun + verb);
+    patch(machine.memory_mut(), &opt.set).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    // Every run starts from the program with the --set patches in
+    let start = machine.snapshot();
+
+    // A one-off run just reports the result
+    if opt.vary.is_empty() && !opt.set.is_empty() {
+        let machine = process(&start, &mut tracer, &limits).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let result = machine.memory().get(opt.target_address).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let report = Report::new(&machine);
+
+        match opt.output_format {
+            Some(format) => println!("{}", Report {outputs: vec![result], ..report}.format(format)),
+            None => println!("[{}] = {}", opt.target_address, result),
+        }
+
+        return Ok(());
+    }
+
+    // Without any ranges, look for the noun and verb as the puzzle asks
+    let noun_and_verb = opt.vary.is_empty();
+    let vary = if noun_and_verb {
+        vec![Vary {address: 1, start: 0, end: 100}, Vary {address: 2, start: 0, end: 100}]
+    } else {
+        opt.vary.clone()
+    };
+
+    let total = combinations(&vary).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "too many combinations to search"))?;
+    let goal = Goal {address: opt.target_address, value: opt.target};
+    let threads = opt.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get()));
+
+    let matches = if tracer.enabled() {
+        seek(&start, &vary, goal, &limits, &mut tracer, opt.all)
+    } else {
+        let tried = AtomicU64::new(0);
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            if opt.progress {
+                scope.spawn(|| show_progress(&tried, total, &done));
+            }
+
+            let matches = seek_parallel(&start, &vary, goal, &limits, threads, opt.all, &tried);
+            done.store(true, Ordering::Relaxed);
+            matches
+        })
+    };
+
+    let matches = matches.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    if matches.is_empty() {
+        let message = format!("nothing tried leaves {} at address {}", goal.value, goal.address);
+        return Err(io::Error::new(io::ErrorKind::NotFound, message));
+    }
+
+    for found in matches {
+        let mut outputs: Vec<i64> = found.patches.iter().map(|patch| patch.value).collect();
+
+        if noun_and_verb {
+            outputs.push(100 * outputs[0] + outputs[1]);
+        }
+
+        match opt.output_format {
+            Some(format) => println!("{}", Report {outputs, ..found.report}.format(format)),
+            None if noun_and_verb => {
+                println!("noun = {}, verb = {}, 100 * {} + {} = {}", outputs[0], outputs[1], outputs[0], outputs[1], outputs[2]);
+            },
+            None => {
+                let patches: Vec<String> = found.patches.iter().map(|patch| patch.to_string()).collect();
+                println!("{}", patches.join(", "));
+            },
+        }
+    }
 
     Ok(())
 }
@@ -106,13 +202,22 @@ fn main() -> std::io::Result<()> {
 mod tests {
     use super::*;
 
+    fn memory(program: &str, limits: &Limits) -> Result<Vec<i64>, IntcodeError> {
+        process(&Snapshot::new(parse_program(program).unwrap()), (), limits).map(|machine| machine.memory().cells().to_vec())
+    }
+
     #[test]
     fn test_process() {
-        assert_eq!(process(tokenise("1,9,10,3,2,3,11,0,99,30,40,50")), vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
-        assert_eq!(process(tokenise("1,0,0,0,99")), vec![2, 0, 0, 0, 99]);
-        assert_eq!(process(tokenise("2,3,0,3,99")), vec![2, 3, 0, 6, 99]);
-        assert_eq!(process(tokenise("2,4,4,5,99,0")), vec![2, 4, 4, 5, 99, 9801]);
-        assert_eq!(process(tokenise("1,1,1,4,99,5,6,0,99")), vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
+        assert_eq!(memory("1,9,10,3,2,3,11,0,99,30,40,50", &Limits::default()), Ok(vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]));
+        assert_eq!(memory("1,0,0,0,99", &Limits::default()), Ok(vec![2, 0, 0, 0, 99]));
+        assert_eq!(memory("2,3,0,3,99", &Limits::default()), Ok(vec![2, 3, 0, 6, 99]));
+        assert_eq!(memory("2,4,4,5,99,0", &Limits::default()), Ok(vec![2, 4, 4, 5, 99, 9801]));
+        assert_eq!(memory("1,1,1,4,99,5,6,0,99", &Limits::default()), Ok(vec![30, 1, 1, 4, 2, 5, 6, 0, 99]));
+        assert_eq!(memory("1,0,0,0,42", &Limits::default()), Err(IntcodeError::UnknownOpcode {ip: 4, instruction: 42}));
+
+        let limits = Limits {steps: Some(100), ..Limits::default()};
+
+        assert_eq!(memory("1105,1,0", &limits), Err(IntcodeError::StepLimitExceeded {ip: 0, instruction: 1105, limit: 100}));
     }
 }
 
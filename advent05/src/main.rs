@@ -1,248 +1,370 @@
+use std::collections::VecDeque;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use std::io::BufReader;
-use std::str::FromStr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use structopt::StructOpt;
 
+use intcode::debugger::Debugger;
+use intcode::error::IntcodeError;
+use intcode::io::{ascii_char, encode_ascii, parse_values, AsciiInput, Input, Output, StdinInput};
+use intcode::machine::{Machine, Status};
+use intcode::opcode::parse_program;
+use intcode::profile::Profiler;
+use intcode::report::{OutputFormat, Report};
+use intcode::session::{self, Session, SessionRecorder};
+use intcode::snapshot::Snapshot;
+use intcode::trace::{open_tracer, TraceFormat, Tracer};
+use intcode::watch::Watchpoint;
+use intcode::watchdog::{parse_seconds, Limits};
+
 #[derive(Debug, StructOpt)]
-#[structopt(name = "advent03", about = "Wire Manhattan distances.")]
+#[structopt(name = "advent05", about = "Run an Intcode program.")]
 struct Opt {
-    /// Input file containing a newline-separated list of wire descriptions
-    #[structopt(name = "FILE")]
-    file_name: String,
+    /// Input file containing a comma-separated Intcode program
+    #[structopt(name = "FILE", required_unless = "resume")]
+    file_name: Option<String>,
+
+    /// Step through the program in an interactive debugger
+    #[structopt(long = "debug")]
+    debug: bool,
+
+    /// How many steps the debugger keeps for reverse-step (default 100000), with 0 meaning
+    /// no limit
+    #[structopt(long = "history-limit")]
+    history_limit: Option<usize>,
+
+    /// Log reads and writes of ADDR[..END][:r|w|rw] to stderr, may be given more than once
+    #[structopt(long = "watch", number_of_values = 1)]
+    watch: Vec<Watchpoint>,
+
+    /// Maximum memory address the program may touch
+    #[structopt(long = "memory-limit")]
+    memory_limit: Option<usize>,
+
+    /// Carry on from a snapshot saved with --save-on-halt instead of starting FILE afresh
+    #[structopt(long = "resume", parse(from_os_str))]
+    resume: Option<PathBuf>,
+
+    /// Save a snapshot when the program halts, runs out of input or leaves the debugger
+    #[structopt(long = "save-on-halt", parse(from_os_str))]
+    save_on_halt: Option<PathBuf>,
+
+    /// Trace each instruction executed as off, text or json
+    #[structopt(long = "trace", default_value = "off")]
+    trace: TraceFormat,
+
+    /// Write the trace to this file instead of stderr
+    #[structopt(long = "trace-file", parse(from_os_str))]
+    trace_file: Option<PathBuf>,
+
+    /// Stop with an error after this many instructions
+    #[structopt(long = "max-steps")]
+    max_steps: Option<u64>,
+
+    /// Stop with an error after this many seconds, including time spent waiting for input
+    #[structopt(long = "timeout", parse(try_from_str = parse_seconds))]
+    timeout: Option<Duration>,
+
+    /// Stop with an error if the program gets stuck in a loop without any input or output
+    #[structopt(long = "detect-loops")]
+    detect_loops: bool,
+
+    /// Print an execution profile and annotated disassembly to stderr when the program stops
+    #[structopt(long = "profile")]
+    profile: bool,
+
+    /// Record every input read and output written to a session file
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Feed the program the inputs from a recorded session, failing if its outputs differ
+    #[structopt(long = "replay", parse(from_os_str), conflicts_with_all = &["debug", "input", "input-file", "input-stdin-all"])]
+    replay: Option<PathBuf>,
+
+    /// Comma-separated input values to feed the program instead of reading stdin, may be
+    /// given more than once
+    #[structopt(long = "input", number_of_values = 1)]
+    input: Vec<String>,
+
+    /// Feed the program input values from this file, separated by commas or whitespace
+    #[structopt(name = "input-file", long = "input-file", parse(from_os_str))]
+    input_file: Option<PathBuf>,
+
+    /// Read all of stdin as input values up front instead of a line at a time
+    #[structopt(name = "input-stdin-all", long = "input-stdin-all")]
+    input_stdin_all: bool,
+
+    /// Print outputs as plain (one per line, the default), csv or json (with the final memory
+    /// and cycle count)
+    #[structopt(long = "output-format", conflicts_with = "debug")]
+    output_format: Option<OutputFormat>,
+
+    /// Talk to the program in ASCII: input is read a line at a time and sent as character
+    /// codes ending with 10, and plain outputs below 128 are printed as characters
+    #[structopt(long = "ascii")]
+    ascii: bool,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum OpcodeMode {
-    Position,
-    Immediate,
+impl Opt {
+    // Left out rather than defaulted, so that it only conflicts with --debug when given
+    fn output_format(&self) -> OutputFormat {
+        self.output_format.unwrap_or(OutputFormat::Plain)
+    }
 }
 
-fn clean_input(input: &str) -> Vec<String> {
-    input.split(",").map(|x| x.replace("\n", "")).collect()
-}
+// How many of the busiest addresses a profile lists
+const HOTTEST: usize = 10;
 
-fn parse_opcode(opcode: &str) -> Result<(&str, Vec<OpcodeMode>), &str> {
-    let parsed_opcode;
-    let mut modes = vec![];
-
-    // If the opcode only has a single char, leave it unchanged
-    if opcode.len() == 1 {
-        parsed_opcode = opcode;
-    } else {
-        // Otherwise, we'll need to do some string surgery to get the opcode value
-        // We're assuming here that opcodes will only be one char and the "0" is
-        // padding--so first let's check what the first digit is
-        if &opcode[(opcode.len() - 2)..opcode.len()] == "99" {
-            parsed_opcode = "99";
-        } else if &opcode[(opcode.len() - 2)..(opcode.len() - 1)] != "0" {
-            return Err("Not an opcode");
-        } else {
-            parsed_opcode = &opcode[(opcode.len() - 1)..];
-        }
+// The trace output, plus the optional profiler and session recorder
+type Tracers<T> = (T, (Option<Profiler>, Option<SessionRecorder>));
+
+// Apply the options shared by normal and debug runs
+fn configure<I: Input, O: Output, T: Tracer>(machine: &mut Machine<I, O, T>, opt: &Opt) {
+    if let Some(limit) = opt.memory_limit {
+        machine.memory_mut().set_limit(limit);
     }
 
-    // Determine how far the instruction pointer moves
-
-    // Extract the number of parameters for an opcode and use that to determine whether
-    // each param should be in immediate or position mode
-    let num_params = match parsed_opcode {
-        "1" => 3,
-        "2" => 3,
-        "3" => 1,
-        "4" => 1,
-        "5" => 2,
-        "6" => 2,
-        "7" => 3,
-        "8" => 3,
-        "99" => 0,
-        _ => 0,
+    for watchpoint in &opt.watch {
+        machine.add_watchpoint(*watchpoint);
+    }
+
+    let limits = Limits {
+        steps: opt.max_steps,
+        timeout: opt.timeout,
+        detect_loops: opt.detect_loops,
     };
 
-    // Initialise the modes to position mode (the default)
-    for _ in 0..num_params {
-        modes.push(OpcodeMode::Position);
-    }
+    limits.apply(machine);
+}
 
-    // If there were paramter mode chars we need to process them now
-    if opcode.len() > 2 {
-        // Extract everything except the last two digits
-        let opcode_modes = &opcode[0..opcode.len() - 2];
-
-        // Now fill with as many parameter modes as we have
-        for (idx, mode) in opcode_modes.chars().enumerate() {
-            modes[opcode_modes.len() - idx - 1] = match mode {
-                '0' => OpcodeMode::Position,
-                '1' => OpcodeMode::Immediate,
-                _ => panic!("Unknown opcode mode '{}'", mode),
-            }
-        }
+// Running out of stdin is an EOF, anything else is a broken program
+fn to_io_error(error: IntcodeError) -> io::Error {
+    let kind = match error {
+        IntcodeError::InputExhausted {..} => io::ErrorKind::UnexpectedEof,
+        _ => io::ErrorKind::InvalidData,
+    };
+
+    io::Error::new(kind, error)
+}
+
+// Each line of text becomes a line of ASCII codes in ASCII mode, and otherwise holds
+// numbers
+fn to_values(text: &str, source: &str, opt: &Opt) -> io::Result<Vec<i64>> {
+    if opt.ascii {
+        return Ok(text.lines().flat_map(encode_ascii).collect());
     }
 
-    // Return the opcode and modes
-    Ok((parsed_opcode, modes))
+    parse_values(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("bad input in {}: {}", source, e)))
 }
 
-fn get_param(instructions: &Vec<String>, ip: usize, mode: OpcodeMode) -> i32 {
-    match mode {
-        OpcodeMode::Position => {
-            let address = usize::from_str(&instructions[ip]).unwrap();
-            i32::from_str(&instructions[address]).unwrap()
-        },
-        OpcodeMode::Immediate => {
-            i32::from_str(&instructions[ip]).unwrap()
-        },
+// Gather the values from --input, --input-file and --input-stdin-all in that order, or
+// None if the program should read stdin as it goes
+fn queued_input(opt: &Opt) -> io::Result<Option<Vec<i64>>> {
+    if opt.input.is_empty() && opt.input_file.is_none() && !opt.input_stdin_all {
+        return Ok(None);
+    }
+
+    let mut values = vec![];
+
+    for text in &opt.input {
+        values.extend(to_values(text, "--input", opt)?);
+    }
+
+    if let Some(path) = &opt.input_file {
+        values.extend(to_values(&fs::read_to_string(path)?, &path.display().to_string(), opt)?);
     }
+
+    if opt.input_stdin_all {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        values.extend(to_values(&text, "stdin", opt)?);
+    }
+
+    Ok(Some(values))
 }
 
-fn main() -> std::io::Result<()> {
-    let opt = Opt::from_args();
-    let file = File::open(opt.file_name)?;
+fn load_program(file_name: &str) -> io::Result<Vec<i64>> {
+    let file = File::open(file_name)?;
     let mut reader = BufReader::new(file);
 
     let mut input = String::new();
     reader.read_line(&mut input)?;
 
-    // Split the input into an array of strings, removing any newlines if they're there
-    let mut instructions = clean_input(&input);
+    // Parse the comma-separated program into memory
+    parse_program(&input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
 
-    // The instruction pointer
-    let mut ip = 0;
+// Load the program or snapshot into a machine with the given devices
+fn start<I: Input, O: Output, T: Tracer>(machine: &mut Machine<I, O, T>, opt: &Opt) -> io::Result<()> {
+    match (&opt.resume, &opt.file_name) {
+        (Some(path), _) => machine.restore(&Snapshot::load(path)?),
+        (None, Some(file_name)) => machine.restore(&Snapshot::new(load_program(file_name)?)),
+        (None, None) => unreachable!("structopt requires FILE without --resume"),
+    }
 
-    // Loop over and process each instruction
-    while ip < instructions.len() {
-        // Get the opcode and parameter modes
-        let (opcode, modes) = match parse_opcode(&instructions[ip]) {
-            Ok(tuple) => tuple,
-            Err(_) => break
-        };
+    configure(machine, opt);
 
-        match opcode {
-            "1" => {
-                // Add
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
-                let out_ptr = u32::from_str(&instructions[ip + 3]).unwrap();
+    Ok(())
+}
 
-                println!("{}:\tADD   {}, {}\t-> {}", ip, lhs_val, rhs_val, out_ptr);
+fn save<I: Input, O: Output, T: Tracer>(machine: &Machine<I, O, T>, opt: &Opt) -> io::Result<()> {
+    if let Some(path) = &opt.save_on_halt {
+        machine.snapshot().save(path)?;
+        eprintln!("Saved snapshot to {}", path.display());
+    }
 
-                instructions[out_ptr as usize] = (lhs_val + rhs_val).to_string();
+    Ok(())
+}
 
-                ip += 4;
-            },
-            "2" => {
-                // Multiply
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
-                let out_ptr = u32::from_str(&instructions[ip + 3]).unwrap();
+// Report the profile and save the session however the run ended. The disassembly is of
+// memory as it ends up, which is what ran last in a program that rewrites itself
+fn finish<I: Input, O: Output, T: Tracer>(machine: &Machine<I, O, Tracers<T>>, opt: &Opt) -> io::Result<()> {
+    let (profiler, recorder) = &machine.tracer().1;
+
+    if let Some(profiler) = profiler {
+        eprintln!("{}", profiler.report(HOTTEST));
+        eprintln!("annotated disassembly:");
+
+        for line in profiler.annotate(machine.memory().cells()) {
+            eprintln!("{}", line);
+        }
+    }
 
-                println!("{}:\tMUL   {}, {}\t-> {}", ip, lhs_val, rhs_val, out_ptr);
+    if let (Some(recorder), Some(path)) = (recorder, &opt.record) {
+        recorder.session().save(path)?;
+        eprintln!("Recorded session to {}", path.display());
+    }
 
-                instructions[out_ptr as usize] = (lhs_val * rhs_val).to_string();
+    Ok(())
+}
 
-                ip += 4;
+// Plain outputs go out as they're produced, which an interactive program needs, while
+// the other formats wait for the run to finish
+fn run<I: Input, T: Tracer>(machine: &mut Machine<I, Vec<i64>, T>, opt: &Opt) -> io::Result<()> {
+    loop {
+        match machine.run_watched(|hit| { eprintln!("watch: {}", hit); true }) {
+            Ok(Status::Output(value)) => {
+                match ascii_char(value) {
+                    _ if opt.output_format() != OutputFormat::Plain => (),
+                    Some(c) if opt.ascii => print!("{}", c),
+                    _ => println!("{}", value),
+                }
             },
-            "3" => {
-                // Input
-                let out_ptr = u32::from_str(&instructions[ip + 1]).unwrap();
+            Ok(_) => return save(machine, opt),
+            // The machine is left on the input instruction, so a snapshot can carry on
+            // once there's more input
+            Err(error @ IntcodeError::InputExhausted {..}) => {
+                save(machine, opt)?;
+                return Err(to_io_error(error));
+            },
+            Err(error) => return Err(to_io_error(error)),
+        }
+    }
+}
 
-                println!("{}:\tIN    {}", ip, out_ptr);
+// Run to the end without stopping for input, since neither stdin nor a queue given up
+// front can be refilled, so asking for more than they have is an error
+fn execute<I: Input, T: Tracer>(machine: &mut Machine<I, Vec<i64>, Tracers<T>>, opt: &Opt) -> io::Result<()> {
+    machine.set_resumable(false);
 
-                // Read the user input
-                let mut value = String::new();
-                io::stdin().read_line(&mut value).unwrap();
+    let result = run(machine, opt);
+    finish(machine, opt)?;
+    result?;
 
-                // Strip the newline
-                let cleaned_value = &value[0..(value.len() - 1)];
+    if opt.output_format() != OutputFormat::Plain {
+        println!("{}", Report::new(machine).format(opt.output_format()));
+    }
 
-                instructions[out_ptr as usize] = u32::from_str(cleaned_value).unwrap().to_string();
+    Ok(())
+}
 
-                ip += 2;
-            },
-            "4" => {
-                // Output
-                let out_ptr = get_param(&instructions, ip + 1, modes[0]);
+fn main() -> std::io::Result<()> {
+    let opt = Opt::from_args();
+    let profiler = if opt.profile { Some(Profiler::new()) } else { None };
+    let recorder = opt.record.as_ref().map(|_| SessionRecorder::new());
+    let tracer = (open_tracer(opt.trace, opt.trace_file.as_deref())?, (profiler, recorder));
+    let queued = queued_input(&opt)?;
 
-                println!("{}:\tOUT   {}", ip, out_ptr);
-                println!("{}", out_ptr);
+    if opt.debug {
+        // Program input comes from the debugger's input command rather than stdin
+        let mut machine = Machine::with_io(vec![], VecDeque::new(), ()).with_tracer(tracer);
 
-                ip += 2;
-            },
-            "5" => {
-                // Jump if true
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
+        start(&mut machine, &opt)?;
 
-                println!("{}:\tJMPT  {}, {}", ip, lhs_val, rhs_val);
+        for value in queued.unwrap_or_default() {
+            machine.feed(value);
+        }
 
-                if lhs_val != 0 {
-                    ip = rhs_val as usize;
-                } else {
-                    ip += 3;
-                }
-            },
-            "6" => {
-                // Jump if false
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
+        let stdin = io::stdin();
+        let mut debugger = Debugger::new(machine);
 
-                println!("{}:\tJMPF  {}, {}", ip, lhs_val, rhs_val);
+        if let Some(limit) = opt.history_limit {
+            debugger.set_history_limit(Some(limit).filter(|&limit| limit > 0));
+        }
 
-                if lhs_val == 0 {
-                    ip = rhs_val as usize;
-                } else {
-                    ip += 3;
-                }
-            },
-            "7" => {
-                // Less than
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
-                let out_ptr = u32::from_str(&instructions[ip + 3]).unwrap();
-
-                println!("{}:\tLT    {}, {}\t-> {}", ip, lhs_val, rhs_val, out_ptr);
-
-                if lhs_val < rhs_val {
-                    instructions[out_ptr as usize] = "1".to_string();
-                } else {
-                    instructions[out_ptr as usize] = "0".to_string();
-                }
+        debugger.repl(stdin.lock(), io::stdout())?;
+        finish(debugger.machine(), &opt)?;
 
-                ip += 4;
-            },
-            "8" => {
-                // Equals
-                let lhs_val = get_param(&instructions, ip + 1, modes[0]);
-                let rhs_val = get_param(&instructions, ip + 2, modes[1]);
-                let out_ptr = u32::from_str(&instructions[ip + 3]).unwrap();
-
-                println!("{}:\tEQ    {}, {}\t-> {}", ip, lhs_val, rhs_val, out_ptr);
-
-                if lhs_val == rhs_val {
-                    instructions[out_ptr as usize] = "1".to_string();
-                } else {
-                    instructions[out_ptr as usize] = "0".to_string();
-                }
+        return save(debugger.machine(), &opt);
+    }
 
-                ip += 4;
-            },
-            "99" => {
-                // Halt
-                println!("{}:\tHALT", ip);
-                break;
-            },
-            _ => {
-                println!("{}:\tERR", ip);
-                break;
-            }
-        }
+    if let Some(path) = &opt.replay {
+        // Inputs come from the session, and running past its end is a divergence
+        let session = Session::load(path)?;
+        let mut machine = Machine::with_io(vec![], VecDeque::new(), vec![]).with_tracer(tracer);
+
+        start(&mut machine, &opt)?;
+
+        let result = session::replay(&mut machine, &session);
+        finish(&machine, &opt)?;
+
+        result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        println!("{}", Report::new(&machine).format(opt.output_format()));
+        eprintln!("Replayed {} events from {}", session.events.len(), path.display());
+
+        return save(&machine, &opt);
     }
 
-    // println!("{:?}", instructions);
+    match queued {
+        Some(values) => {
+            let given = values.len();
+            let mut machine = Machine::with_io(vec![], VecDeque::new(), vec![]).with_tracer(tracer);
 
-    Ok(())
+            start(&mut machine, &opt)?;
+
+            for value in values {
+                machine.feed(value);
+            }
+
+            execute(&mut machine, &opt).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), format!("ran out of input after the {} values given: {}", given, e)),
+                _ => e,
+            })
+        },
+        None if opt.ascii => {
+            let mut machine = Machine::with_io(vec![], AsciiInput::new(io::stdin().lock()), vec![]).with_tracer(tracer);
+
+            start(&mut machine, &opt)?;
+            execute(&mut machine, &opt)
+        },
+        None => {
+            let mut machine = Machine::with_io(vec![], StdinInput::new(), vec![]).with_tracer(tracer);
+
+            start(&mut machine, &opt)?;
+
+            // A line that isn't a number stops the input the same as end of file does,
+            // but it's the bad line that needs reporting
+            execute(&mut machine, &opt).map_err(|e| match machine.input().error() {
+                Some(error) => io::Error::new(io::ErrorKind::InvalidData, error),
+                None => e,
+            })
+        },
+    }
 }
 
 
@@ -251,18 +373,11 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_get_param() {
-        let test_data = clean_input("1002,4,3,4,33");
+    fn test_options() {
+        let opt = Opt::from_iter_safe(&["advent05", "--debug", "test1.txt"]).unwrap();
 
-        assert_eq!(get_param(&test_data, 1, OpcodeMode::Position), 33);
-        assert_eq!(get_param(&test_data, 1, OpcodeMode::Immediate), 4);
-    }
-
-    #[test]
-    fn test_parse_opcode() {
-        assert_eq!(parse_opcode("1"), ("1", vec![OpcodeMode::Position, OpcodeMode::Position, OpcodeMode::Position]));
-        assert_eq!(parse_opcode("2"), ("2", vec![OpcodeMode::Position, OpcodeMode::Position, OpcodeMode::Position]));
-        assert_eq!(parse_opcode("3"), ("3", vec![OpcodeMode::Position]));
-        assert_eq!(parse_opcode("4"), ("4", vec![OpcodeMode::Position]));
+        assert!(opt.debug);
+        assert_eq!(opt.output_format(), OutputFormat::Plain);
+        assert!(Opt::from_iter_safe(&["advent05", "--debug", "--output-format", "json", "test1.txt"]).is_err());
     }
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::opcode::{from_mnemonic, param_count, write_param, OpcodeMode};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: String) -> AsmError {
+        AsmError {line, message}
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// A number, a label or a label plus an offset
+#[derive(Clone, Debug, PartialEq)]
+struct Expr {
+    label: Option<String>,
+    offset: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Instruction { line: usize, opcode: i64, operands: Vec<(OpcodeMode, Expr)> },
+    Data { line: usize, values: Vec<Expr> },
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn parse_expr(text: &str, line: usize) -> Result<Expr, AsmError> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if let Ok(value) = i64::from_str(&text) {
+        return Ok(Expr {label: None, offset: value});
+    }
+
+    // Split off any offset following the label
+    let (label, offset) = match text.find(['+', '-']) {
+        Some(idx) => (&text[..idx], &text[idx..]),
+        None => (&text[..], ""),
+    };
+
+    if !is_label(label) {
+        return Err(AsmError::new(line, format!("'{}' is not a number or label", text)));
+    }
+
+    let offset = if offset.is_empty() {
+        0
+    } else {
+        i64::from_str(offset).map_err(|_| AsmError::new(line, format!("bad offset in '{}'", text)))?
+    };
+
+    Ok(Expr {label: Some(label.to_string()), offset})
+}
+
+// Operands are written #5 for immediate, [7] for position and rb+3 for relative
+fn parse_operand(text: &str, line: usize) -> Result<(OpcodeMode, Expr), AsmError> {
+    let text = text.trim();
+
+    if let Some(rest) = text.strip_prefix('#') {
+        Ok((OpcodeMode::Immediate, parse_expr(rest, line)?))
+    } else if text.starts_with('[') && text.ends_with(']') {
+        Ok((OpcodeMode::Position, parse_expr(&text[1..text.len() - 1], line)?))
+    } else if matches!(text.get(..2), Some(prefix) if prefix.eq_ignore_ascii_case("rb")) {
+        let rest: String = text[2..].chars().filter(|c| !c.is_whitespace()).collect();
+
+        if rest.is_empty() {
+            Ok((OpcodeMode::Relative, Expr {label: None, offset: 0}))
+        } else if rest.starts_with('+') || rest.starts_with('-') {
+            let offset = i64::from_str(&rest).map_err(|_| AsmError::new(line, format!("bad relative offset '{}'", text)))?;
+            Ok((OpcodeMode::Relative, Expr {label: None, offset}))
+        } else {
+            Err(AsmError::new(line, format!("bad relative operand '{}'", text)))
+        }
+    } else {
+        Err(AsmError::new(line, format!("operand '{}' needs a mode: #imm, [pos] or rb+offset", text)))
+    }
+}
+
+// Turn each line into an item, recording label addresses as we go
+fn parse(source: &str) -> Result<(Vec<Item>, HashMap<String, i64>), AsmError> {
+    let mut items = vec![];
+    let mut labels = HashMap::new();
+    let mut address = 0;
+
+    for (idx, raw_line) in source.lines().enumerate() {
+        let line = idx + 1;
+
+        // Strip comments
+        let mut text = raw_line.split(';').next().unwrap_or("").trim();
+
+        // Peel off any label definitions
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+
+            if !is_label(label) {
+                break;
+            }
+
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AsmError::new(line, format!("label '{}' is defined twice", label)));
+            }
+
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (name, rest) = match text.find(char::is_whitespace) {
+            Some(idx) => (&text[..idx], text[idx..].trim()),
+            None => (text, ""),
+        };
+
+        // The trace's "a, b -> c" style is accepted as well as plain commas
+        let rest = rest.replace("->", ",");
+        let args: Vec<&str> = rest.split(',').map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect();
+
+        if name.eq_ignore_ascii_case("DATA") {
+            let values = args.iter().map(|arg| parse_expr(arg, line)).collect::<Result<Vec<_>, _>>()?;
+
+            address += values.len() as i64;
+            items.push(Item::Data {line, values});
+            continue;
+        }
+
+        let opcode = from_mnemonic(name).ok_or_else(|| AsmError::new(line, format!("unknown mnemonic '{}'", name)))?;
+        let count = param_count(opcode).unwrap_or(0);
+
+        if args.len() != count {
+            return Err(AsmError::new(line, format!("{} takes {} operands but was given {}", name.to_uppercase(), count, args.len())));
+        }
+
+        let operands = args.iter().map(|arg| parse_operand(arg, line)).collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(idx) = write_param(opcode) {
+            if operands[idx].0 == OpcodeMode::Immediate {
+                return Err(AsmError::new(line, format!("{} cannot write to an immediate operand", name.to_uppercase())));
+            }
+        }
+
+        address += 1 + count as i64;
+        items.push(Item::Instruction {line, opcode, operands});
+    }
+
+    Ok((items, labels))
+}
+
+fn resolve(expr: &Expr, labels: &HashMap<String, i64>, line: usize) -> Result<i64, AsmError> {
+    match &expr.label {
+        None => Ok(expr.offset),
+        Some(label) => match labels.get(label) {
+            Some(address) => address.checked_add(expr.offset).ok_or_else(|| AsmError::new(line, "label offset out of range".to_string())),
+            None => Err(AsmError::new(line, format!("undefined label '{}'", label))),
+        },
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let (items, labels) = parse(source)?;
+    let mut program = vec![];
+
+    for item in &items {
+        match item {
+            Item::Instruction {line, opcode, operands} => {
+                let mut instruction = *opcode;
+                let mut factor = 100;
+
+                for (mode, _) in operands {
+                    instruction += factor * match mode {
+                        OpcodeMode::Position => 0,
+                        OpcodeMode::Immediate => 1,
+                        OpcodeMode::Relative => 2,
+                    };
+                    factor *= 10;
+                }
+
+                program.push(instruction);
+
+                for (_, expr) in operands {
+                    program.push(resolve(expr, &labels, *line)?);
+                }
+            },
+            Item::Data {line, values} => {
+                for expr in values {
+                    program.push(resolve(expr, &labels, *line)?);
+                }
+            },
+        }
+    }
+
+    Ok(program)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Machine, Status};
+
+    const COUNTDOWN: &str = "
+        ; Count down from 3, printing each value
+        start:  OUT  [counter]
+                ADD  [counter], #-1 -> [counter]
+                JMPT [counter], #start
+                HALT
+        counter: DATA 3
+    ";
+
+    #[test]
+    fn test_assemble() {
+        let program = assemble(COUNTDOWN).unwrap();
+
+        assert_eq!(program, vec![4, 10, 1001, 10, -1, 10, 1005, 10, 0, 99, 3]);
+
+        let mut machine = Machine::new(program);
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.output(), &vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(assemble("ARB #5\nADD rb-1, rb+2 -> rb\nIN rb+1"), Ok(vec![109, 5, 22201, -1, 2, 0, 203, 1]));
+        assert_eq!(assemble("x: DATA x+2, 7, -1"), Ok(vec![2, 7, -1]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("HALT\nJUMP #0").unwrap_err().line, 2);
+        assert!(assemble("ADD #1, #2, #3").unwrap_err().message.contains("immediate"));
+        assert!(assemble("OUT [nowhere]").unwrap_err().message.contains("undefined label"));
+        assert!(assemble("OUT #1, #2").unwrap_err().message.contains("takes 1 operands"));
+        assert!(assemble("a: HALT\na: HALT").unwrap_err().message.contains("defined twice"));
+
+        let error = assemble("HALT\nx: DATA x+9223372036854775807").unwrap_err();
+
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("label offset out of range"));
+    }
+}
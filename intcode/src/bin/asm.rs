@@ -0,0 +1,33 @@
+use std::fs;
+use std::io;
+
+use structopt::StructOpt;
+
+use intcode::asm::assemble;
+use intcode::opcode::format_program;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "asm", about = "Assemble Intcode mnemonics into a program.")]
+struct Opt {
+    /// Input file containing Intcode assembly
+    #[structopt(name = "FILE")]
+    file_name: String,
+
+    /// Write the program to this file instead of stdout
+    #[structopt(short = "o", long = "output")]
+    output: Option<String>,
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+    let source = fs::read_to_string(&opt.file_name)?;
+
+    let program = assemble(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    match opt.output {
+        Some(file_name) => fs::write(file_name, format_program(&program) + "\n")?,
+        None => println!("{}", format_program(&program)),
+    }
+
+    Ok(())
+}
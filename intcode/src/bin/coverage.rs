@@ -0,0 +1,71 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use intcode::coverage::Coverage;
+use intcode::machine::{Machine, Status};
+use intcode::opcode::parse_program;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "coverage", about = "Show which parts of an Intcode program a set of runs exercise.")]
+struct Opt {
+    /// Input file containing a comma-separated Intcode program
+    #[structopt(name = "FILE")]
+    file_name: String,
+
+    /// Comma-separated input values for one run, may be given once per run
+    #[structopt(long = "run", number_of_values = 1)]
+    runs: Vec<String>,
+
+    /// Also write a JSON summary to this file
+    #[structopt(long = "summary", parse(from_os_str))]
+    summary: Option<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+    let input = fs::read_to_string(&opt.file_name)?;
+
+    let program = parse_program(&input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    // Without any runs given, run once with no input
+    let runs = if opt.runs.is_empty() { vec![String::new()] } else { opt.runs.clone() };
+    let mut coverage = Coverage::new();
+    let mut memory = program.clone();
+
+    for (idx, run) in runs.iter().enumerate() {
+        let values = match run.trim() {
+            "" => vec![],
+            values => parse_program(values).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        };
+
+        let mut machine = Machine::new(program.clone()).with_tracer(&mut coverage);
+
+        for value in values {
+            machine.feed(value);
+        }
+
+        // A run that stops early still counts towards coverage
+        match machine.run_until_blocked() {
+            Ok(Status::Halted) => (),
+            Ok(status) => eprintln!("run {}: stopped with {:?} at {}", idx + 1, status, machine.ip()),
+            Err(error) => eprintln!("run {}: {}", idx + 1, error),
+        }
+
+        memory = machine.memory().cells().to_vec();
+    }
+
+    // List memory as the last run left it, so code the program rewrote before running
+    // shows up as it actually ran
+    for line in coverage.annotate(&memory) {
+        println!("{}", line);
+    }
+
+    if let Some(path) = &opt.summary {
+        fs::write(path, coverage.summary(&memory) + "\n")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use std::fs;
+use std::io;
+
+use structopt::StructOpt;
+
+use intcode::disasm::disassemble;
+use intcode::opcode::parse_program;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "disasm", about = "List an Intcode program without running it.")]
+struct Opt {
+    /// Input file containing a comma-separated Intcode program
+    #[structopt(name = "FILE")]
+    file_name: String,
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+    let input = fs::read_to_string(&opt.file_name)?;
+
+    let program = parse_program(&input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    for line in disassemble(&program) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+
+use super::error::IntcodeError;
+use super::machine::{Machine, Status};
+use super::search::sweep;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CircuitError {
+    // A machine asked for more input than the circuit had to give it
+    Stalled(usize),
+    Failed(usize, IntcodeError),
+    NoOutput,
+}
+
+impl fmt::Display for CircuitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CircuitError::Stalled(idx) => write!(f, "machine {} stalled waiting for input", idx),
+            CircuitError::Failed(idx, error) => write!(f, "machine {} failed: {}", idx, error),
+            CircuitError::NoOutput => write!(f, "the circuit produced no output"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Circuit {
+    machines: Vec<Machine<VecDeque<i64>, ()>>,
+    feedback: bool,
+}
+
+impl Circuit {
+    // Wire up one copy of the program per phase setting, each fed its phase first
+    pub fn new(program: &[i64], phases: &[i64], feedback: bool) -> Circuit {
+        let machines = phases.iter().map(|&phase| {
+            let mut machine = Machine::with_io(program.to_vec(), VecDeque::new(), ());
+            machine.feed(phase);
+            machine
+        }).collect();
+
+        Circuit {machines, feedback}
+    }
+
+    pub fn machines(&self) -> &[Machine<VecDeque<i64>, ()>] {
+        &self.machines
+    }
+
+    // Pass a signal down the chain, looping the last output back into the first machine
+    // until the last one halts if this is a feedback circuit
+    pub fn run(&mut self, signal: i64) -> Result<i64, CircuitError> {
+        if self.machines.is_empty() {
+            return Err(CircuitError::NoOutput);
+        }
+
+        let mut signal = signal;
+        let mut last_output = None;
+        let last_idx = self.machines.len() - 1;
+
+        loop {
+            for (idx, machine) in self.machines.iter_mut().enumerate() {
+                machine.feed(signal);
+
+                match machine.run() {
+                    Ok(Status::Output(value)) => {
+                        signal = value;
+
+                        if idx == last_idx {
+                            last_output = Some(value);
+                        }
+                    },
+                    Ok(Status::NeedsInput) => return Err(CircuitError::Stalled(idx)),
+                    Ok(_) => (),
+                    Err(error) => return Err(CircuitError::Failed(idx, error)),
+                }
+            }
+
+            if !self.feedback || self.machines[last_idx].status() == Status::Halted {
+                break;
+            }
+        }
+
+        last_output.ok_or(CircuitError::NoOutput)
+    }
+}
+
+// Every ordering of the given values
+pub fn permutations(values: &[i64]) -> Vec<Vec<i64>> {
+    if values.len() <= 1 {
+        return vec![values.to_vec()];
+    }
+
+    let mut result = vec![];
+
+    for idx in 0..values.len() {
+        let mut rest = values.to_vec();
+        let first = rest.remove(idx);
+
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, first);
+            result.push(permutation);
+        }
+    }
+
+    result
+}
+
+// Try every ordering of the phase settings and return the one giving the highest signal
+pub fn max_signal(program: &[i64], phases: &[i64], feedback: bool) -> Result<(Vec<i64>, i64), CircuitError> {
+    let mut best: Option<(Vec<i64>, i64)> = None;
+
+    for permutation in permutations(phases) {
+        let signal = Circuit::new(program, &permutation, feedback).run(0)?;
+
+        match best {
+            Some((_, best_signal)) if best_signal >= signal => (),
+            _ => best = Some((permutation, signal)),
+        }
+    }
+
+    best.ok_or(CircuitError::NoOutput)
+}
+
+// As max_signal, but running the orderings across a number of threads. The signals are
+// gathered in order, so an error or a tie comes out the same as it would one at a time
+pub fn max_signal_parallel(program: &[i64], phases: &[i64], feedback: bool, threads: usize) -> Result<(Vec<i64>, i64), CircuitError> {
+    let permutations = permutations(phases);
+
+    let signals = sweep(permutations.len() as u64, threads, false, &AtomicU64::new(0), |index| {
+        Some(Circuit::new(program, &permutations[index as usize], feedback).run(0))
+    });
+
+    let mut best: Option<(usize, i64)> = None;
+
+    for (index, signal) in signals.into_iter().enumerate() {
+        let signal = signal?;
+
+        match best {
+            Some((_, best_signal)) if best_signal >= signal => (),
+            _ => best = Some((index, signal)),
+        }
+    }
+
+    best.map(|(index, signal)| (permutations[index].clone(), signal)).ok_or(CircuitError::NoOutput)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(permutations(&[1, 2, 3]).len(), 6);
+        assert_eq!(permutations(&[1, 2]), vec![vec![1, 2], vec![2, 1]]);
+    }
+
+    #[test]
+    fn test_series() {
+        let program = parse_program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0").unwrap();
+
+        assert_eq!(Circuit::new(&program, &[4, 3, 2, 1, 0], false).run(0), Ok(43210));
+        assert_eq!(max_signal(&program, &[0, 1, 2, 3, 4], false), Ok((vec![4, 3, 2, 1, 0], 43210)));
+        assert_eq!(max_signal_parallel(&program, &[0, 1, 2, 3, 4], false, 4), max_signal(&program, &[0, 1, 2, 3, 4], false));
+    }
+
+    #[test]
+    fn test_feedback() {
+        let program = parse_program("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5").unwrap();
+
+        assert_eq!(max_signal(&program, &[5, 6, 7, 8, 9], true), Ok((vec![9, 8, 7, 6, 5], 139629729)));
+        assert_eq!(max_signal_parallel(&program, &[5, 6, 7, 8, 9], true, 4), max_signal(&program, &[5, 6, 7, 8, 9], true));
+        // A series program run as a feedback loop stops short, and so should the parallel one
+        assert_eq!(max_signal_parallel(&program, &[5, 6, 7, 8, 9], false, 4), max_signal(&program, &[5, 6, 7, 8, 9], false));
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::disasm::{disassemble_from, Line};
+use super::trace::{TraceEvent, Tracer};
+
+// Which ways a conditional jump has gone
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Branch {
+    pub taken: bool,
+    pub not_taken: bool,
+}
+
+impl Branch {
+    pub fn one_way(&self) -> bool {
+        self.taken != self.not_taken
+    }
+}
+
+// Addresses executed and branch directions seen, fed by the tracer hook. Hand the same
+// Coverage to several machines (by &mut) to combine runs
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    executed: BTreeSet<usize>,
+    branches: BTreeMap<usize, Branch>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    pub fn executed(&self, address: usize) -> bool {
+        self.executed.contains(&address)
+    }
+
+    pub fn branch(&self, address: usize) -> Option<Branch> {
+        self.branches.get(&address).cloned()
+    }
+
+    // Start from everything that ran as well as address 0, so code only reached through
+    // computed jumps still gets listed as code
+    fn listing(&self, program: &[i64]) -> Vec<Line> {
+        let roots: Vec<usize> = Some(0).into_iter().chain(self.executed.iter().cloned()).collect();
+        disassemble_from(program, &roots)
+    }
+
+    // Instructions in the program's disassembly that never ran
+    pub fn unexecuted(&self, program: &[i64]) -> Vec<usize> {
+        self.listing(program).iter().filter_map(|line| match line {
+            Line::Code(instruction) if !self.executed(instruction.address) => Some(instruction.address),
+            _ => None,
+        }).collect()
+    }
+
+    // Conditional jumps that ran but only ever went one way
+    pub fn one_way(&self) -> Vec<(usize, Branch)> {
+        self.branches.iter().filter(|(_, branch)| branch.one_way()).map(|(&address, &branch)| (address, branch)).collect()
+    }
+
+    // The disassembly with a marker per instruction: + ran, - never ran, ~ a jump that
+    // only went one way
+    pub fn annotate(&self, program: &[i64]) -> Vec<String> {
+        self.listing(program).iter().map(|line| match line {
+            Line::Code(instruction) => match self.branch(instruction.address) {
+                Some(branch) if branch.one_way() => {
+                    let way = if branch.taken { "always taken" } else { "never taken" };
+                    format!("~ {}\t; {}", line, way)
+                },
+                _ if self.executed(instruction.address) => format!("+ {}", line),
+                _ => format!("- {}", line),
+            },
+            Line::Data {..} => format!("  {}", line),
+        }).collect()
+    }
+
+    // A one-line JSON summary for scripts to check
+    pub fn summary(&self, program: &[i64]) -> String {
+        let instructions = self.listing(program).iter().filter(|line| matches!(line, Line::Code(_))).count();
+        let unexecuted: Vec<String> = self.unexecuted(program).iter().map(|address| address.to_string()).collect();
+
+        let one_way: Vec<String> = self.one_way().iter().map(|(address, branch)| {
+            format!("{{\"address\":{},\"taken\":{},\"not_taken\":{}}}", address, branch.taken, branch.not_taken)
+        }).collect();
+
+        format!(
+            "{{\"instructions\":{},\"executed\":{},\"unexecuted\":[{}],\"branches\":{},\"one_way\":[{}]}}",
+            instructions,
+            instructions - unexecuted.len(),
+            unexecuted.join(","),
+            self.branches.len(),
+            one_way.join(","),
+        )
+    }
+}
+
+impl Tracer for Coverage {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.executed.insert(event.ip);
+
+        let taken = match event.opcode {
+            5 => event.operands[0] != 0,
+            6 => event.operands[0] == 0,
+            _ => return,
+        };
+
+        let branch = self.branches.entry(event.ip).or_default();
+
+        if taken {
+            branch.taken = true;
+        } else {
+            branch.not_taken = true;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+    use crate::opcode::parse_program;
+
+    // Output 1 if the input is 8, otherwise 0
+    const EQUALS_8: &str = "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9";
+
+    fn run(coverage: &mut Coverage, program: &[i64], input: i64) {
+        let mut machine = Machine::new(program.to_vec()).with_tracer(&mut *coverage);
+        machine.feed(input);
+        machine.run_until_blocked().unwrap();
+    }
+
+    #[test]
+    fn test_coverage() {
+        let program = parse_program(EQUALS_8).unwrap();
+        let mut coverage = Coverage::new();
+
+        run(&mut coverage, &program, 0);
+
+        assert_eq!(coverage.unexecuted(&program), vec![5]);
+        assert_eq!(coverage.one_way(), vec![(2, Branch {taken: true, not_taken: false})]);
+        assert_eq!(coverage.annotate(&program)[..3], ["+ 0:\tIN    [12]", "~ 2:\tJMPF  [12], [15]\t; always taken", "- 5:\tADD   [13], [14]\t-> [13]"]);
+
+        run(&mut coverage, &program, 1);
+
+        assert!(coverage.unexecuted(&program).is_empty());
+        assert!(coverage.one_way().is_empty());
+        assert_eq!(coverage.summary(&program), "{\"instructions\":5,\"executed\":5,\"unexecuted\":[],\"branches\":1,\"one_way\":[]}");
+    }
+}
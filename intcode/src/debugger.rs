@@ -0,0 +1,516 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use super::disasm::decode;
+use super::error::IntcodeError;
+use super::io::Output;
+use super::machine::{Machine, Status};
+use super::trace::Tracer;
+use super::watch::Watchpoint;
+
+const HELP: &str = "\
+break [ADDR]          set a breakpoint, or list them with no address
+delete ADDR           remove a breakpoint
+watch ADDR[..ADDR][:r|w|rw]
+                      stop when the program reads or writes memory there
+unwatch ADDR          remove any watchpoints covering ADDR
+step [N]              execute N instructions (default 1)
+continue              run until a breakpoint, output request, halt or failure
+reverse-step [N]      undo N instructions or sets (default 1)
+reverse-continue      undo instructions back to the previous breakpoint
+last-write ADDR       show the most recent instruction to write ADDR
+print ADDR[..ADDR]    show memory, with ranges excluding the end address and at most
+                      4096 cells long
+set ADDR VALUE        write VALUE to memory
+input VALUE           queue a value for the program to read
+regs                  show the instruction pointer, relative base and status
+disasm [N]            list N instructions from the instruction pointer (default 5)
+quit                  leave the debugger";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Break(Option<usize>),
+    Delete(usize),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    Step(usize),
+    Continue,
+    ReverseStep(usize),
+    ReverseContinue,
+    LastWrite(usize),
+    Print(usize, usize),
+    Set(usize, i64),
+    Input(i64),
+    Regs,
+    Disasm(usize),
+    Help,
+    Quit,
+}
+
+fn parse_arg<T: FromStr>(arg: Option<&str>, what: &str) -> Result<T, String> {
+    match arg {
+        Some(text) => T::from_str(text).map_err(|_| format!("'{}' is not a valid {}", text, what)),
+        None => Err(format!("missing {}", what)),
+    }
+}
+
+fn parse_optional<T: FromStr>(arg: Option<&str>, what: &str, default: T) -> Result<T, String> {
+    match arg {
+        Some(_) => parse_arg(arg, what),
+        None => Ok(default),
+    }
+}
+
+// The most cells one print shows, so a mistyped range can't build billions of lines
+const PRINT_LIMIT: usize = 4096;
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let arg = words.next();
+
+    let command = match name {
+        "break" | "b" => Command::Break(match arg {
+            Some(_) => Some(parse_arg(arg, "address")?),
+            None => None,
+        }),
+        "delete" | "d" => Command::Delete(parse_arg(arg, "address")?),
+        "watch" | "w" => Command::Watch(Watchpoint::from_str(arg.ok_or("missing address")?)?),
+        "unwatch" => Command::Unwatch(parse_arg(arg, "address")?),
+        "step" | "s" => Command::Step(parse_optional(arg, "count", 1)?),
+        "continue" | "c" => Command::Continue,
+        "reverse-step" | "rs" => Command::ReverseStep(parse_optional(arg, "count", 1)?),
+        "reverse-continue" | "rc" => Command::ReverseContinue,
+        "last-write" | "lw" => Command::LastWrite(parse_arg(arg, "address")?),
+        "print" | "p" => {
+            let range = arg.ok_or("missing address")?;
+
+            match range.find("..") {
+                Some(idx) => {
+                    let start: usize = parse_arg(Some(&range[..idx]), "address")?;
+                    let end: usize = parse_arg(Some(&range[idx + 2..]), "address")?;
+
+                    if end.saturating_sub(start) > PRINT_LIMIT {
+                        return Err(format!("'{}' is more than {} cells", range, PRINT_LIMIT));
+                    }
+
+                    Command::Print(start, end)
+                },
+                None => {
+                    let address: usize = parse_arg(Some(range), "address")?;
+                    let end = address.checked_add(1).ok_or_else(|| format!("'{}' is not a valid address", range))?;
+                    Command::Print(address, end)
+                },
+            }
+        },
+        "set" => Command::Set(parse_arg(arg, "address")?, parse_arg(words.next(), "value")?),
+        "input" | "i" => Command::Input(parse_arg(arg, "value")?),
+        "regs" | "r" => Command::Regs,
+        "disasm" | "x" => Command::Disasm(parse_optional(arg, "count", 5)?),
+        "help" | "h" | "?" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ => return Err(format!("unknown command '{}', try 'help'", name)),
+    };
+
+    Ok(command)
+}
+
+pub struct Debugger<O: Output, T: Tracer = ()> {
+    machine: Machine<VecDeque<i64>, O, T>,
+    breakpoints: BTreeSet<usize>,
+}
+
+// How many steps back the debugger can go by default, which keeps memory use in check
+// when continuing through long runs
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+impl<O: Output, T: Tracer> Debugger<O, T> {
+    // Recording is switched on so the program can be stepped backwards
+    pub fn new(mut machine: Machine<VecDeque<i64>, O, T>) -> Debugger<O, T> {
+        machine.set_recording(true);
+        machine.set_history_limit(Some(DEFAULT_HISTORY_LIMIT));
+
+        Debugger {machine, breakpoints: BTreeSet::new()}
+    }
+
+    // None keeps every step
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.machine.set_history_limit(limit);
+    }
+
+    pub fn machine(&self) -> &Machine<VecDeque<i64>, O, T> {
+        &self.machine
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    // Describe a status the machine stopped on
+    fn describe(&self, status: Status) -> String {
+        match status {
+            Status::Running | Status::Break => format!("Stopped at {}", self.machine.ip()),
+            Status::Halted => format!("Halted at {}", self.machine.ip()),
+            Status::NeedsInput => format!("Waiting for input at {}, use 'input VALUE'", self.machine.ip()),
+            Status::Output(value) => format!("Output: {}", value),
+        }
+    }
+
+    fn describe_error(&self, error: IntcodeError) -> String {
+        format!("Failed at {}: {}", self.machine.ip(), error)
+    }
+
+    // Add a line per watchpoint hit in the last step, returning whether there were any
+    fn report_watch_hits(&self, lines: &mut Vec<String>) -> bool {
+        for hit in self.machine.watch_hits() {
+            lines.push(format!("Watchpoint: {}", hit));
+        }
+
+        !self.machine.watch_hits().is_empty()
+    }
+
+    fn step(&mut self, count: usize) -> String {
+        let mut lines = vec![];
+
+        for _ in 0..count {
+            match self.machine.step() {
+                Ok(Status::Running) => (),
+                Ok(Status::Output(value)) => lines.push(format!("Output: {}", value)),
+                Ok(status) => {
+                    lines.push(self.describe(status));
+                    return lines.join("\n");
+                },
+                Err(error) => {
+                    lines.push(self.describe_error(error));
+                    return lines.join("\n");
+                },
+            }
+
+            if self.report_watch_hits(&mut lines) {
+                break;
+            }
+        }
+
+        lines.push(self.describe(Status::Running));
+        lines.join("\n")
+    }
+
+    fn cont(&mut self) -> String {
+        let mut lines = vec![];
+
+        loop {
+            match self.machine.step() {
+                Ok(Status::Running) => (),
+                Ok(Status::Output(value)) => lines.push(format!("Output: {}", value)),
+                Ok(status) => {
+                    lines.push(self.describe(status));
+                    break;
+                },
+                Err(error) => {
+                    lines.push(self.describe_error(error));
+                    break;
+                },
+            }
+
+            if self.report_watch_hits(&mut lines) {
+                lines.push(self.describe(Status::Running));
+                break;
+            }
+
+            if self.breakpoints.contains(&self.machine.ip()) {
+                lines.push(format!("Breakpoint at {}", self.machine.ip()));
+                break;
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    // Undo one instruction, handing back any input it read. Returns false at the start
+    // of the recorded history
+    fn undo(&mut self) -> bool {
+        match self.machine.reverse_step() {
+            Some(step) => {
+                if let Some(value) = step.input {
+                    self.machine.input_mut().push_front(value);
+                }
+
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn reverse_step(&mut self, count: usize) -> String {
+        for _ in 0..count {
+            if !self.undo() {
+                return format!("Reached the start of history at {}", self.machine.ip());
+            }
+        }
+
+        self.describe(Status::Running)
+    }
+
+    fn reverse_cont(&mut self) -> String {
+        while self.undo() {
+            if self.breakpoints.contains(&self.machine.ip()) {
+                return format!("Breakpoint at {}", self.machine.ip());
+            }
+        }
+
+        format!("Reached the start of history at {}", self.machine.ip())
+    }
+
+    fn last_write(&self, address: usize) -> String {
+        match self.machine.last_write(address) {
+            Some((step, write)) => format!(
+                "{} last written at cycle {} by {}: {} -> {}",
+                address, step.cycle, step.ip, write.old, write.new,
+            ),
+            None => format!("No recorded write to {}", address),
+        }
+    }
+
+    fn print(&self, start: usize, end: usize) -> String {
+        let mut lines = vec![];
+
+        for address in (start..end).take(PRINT_LIMIT) {
+            match self.machine.memory().get(address) {
+                Ok(value) => lines.push(format!("{}:\t{}", address, value)),
+                Err(error) => {
+                    lines.push(error.to_string());
+                    break;
+                },
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    fn disasm(&self, count: usize) -> String {
+        let mut lines = vec![];
+        let mut address = self.machine.ip();
+
+        // Decode from live memory, since the program may have rewritten itself
+        let cells = self.machine.memory().cells();
+
+        for _ in 0..count {
+            let marker = if address == self.machine.ip() { "=>" } else { "  " };
+
+            match decode(cells, address) {
+                Some(instruction) => {
+                    lines.push(format!("{} {}:\t{}", marker, address, instruction));
+                    address += instruction.size();
+                },
+                None => {
+                    match cells.get(address) {
+                        Some(value) => lines.push(format!("{} {}:\tDATA  {}", marker, address, value)),
+                        None => break,
+                    }
+                    address += 1;
+                },
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn execute(&mut self, command: Command) -> String {
+        match command {
+            Command::Break(Some(address)) => {
+                self.breakpoints.insert(address);
+                format!("Breakpoint set at {}", address)
+            },
+            Command::Break(None) => {
+                if self.breakpoints.is_empty() {
+                    return "No breakpoints".to_string();
+                }
+
+                let addresses: Vec<String> = self.breakpoints.iter().map(|a| a.to_string()).collect();
+                format!("Breakpoints: {}", addresses.join(", "))
+            },
+            Command::Delete(address) => {
+                if self.breakpoints.remove(&address) {
+                    format!("Breakpoint at {} deleted", address)
+                } else {
+                    format!("No breakpoint at {}", address)
+                }
+            },
+            Command::Watch(watchpoint) => {
+                self.machine.add_watchpoint(watchpoint);
+                format!("Watching {}..{}", watchpoint.start, watchpoint.end)
+            },
+            Command::Unwatch(address) => {
+                self.machine.remove_watchpoint(address);
+                format!("No longer watching {}", address)
+            },
+            Command::Step(count) => self.step(count),
+            Command::Continue => self.cont(),
+            Command::ReverseStep(count) => self.reverse_step(count),
+            Command::ReverseContinue => self.reverse_cont(),
+            Command::LastWrite(address) => self.last_write(address),
+            Command::Print(start, end) => self.print(start, end),
+            Command::Set(address, value) => match self.machine.poke(address, value) {
+                Ok(()) => format!("{}:\t{}", address, value),
+                Err(error) => error.to_string(),
+            },
+            Command::Input(value) => {
+                self.machine.feed(value);
+                format!("Queued input {}", value)
+            },
+            Command::Regs => format!(
+                "ip = {}, rb = {}, status = {:?}",
+                self.machine.ip(), self.machine.relative_base(), self.machine.status(),
+            ),
+            Command::Disasm(count) => self.disasm(count),
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        }
+    }
+
+    // Read commands until quit or end of input. A blank line repeats the last command
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut last = None;
+
+        write!(output, "(debug) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+
+            let command = if line.trim().is_empty() {
+                last.clone().ok_or_else(|| "no previous command".to_string())
+            } else {
+                parse_command(&line)
+            };
+
+            match command {
+                Ok(Command::Quit) => return Ok(()),
+                Ok(command) => {
+                    writeln!(output, "{}", self.execute(command.clone()))?;
+                    last = Some(command);
+                },
+                Err(message) => writeln!(output, "{}", message)?,
+            }
+
+            write!(output, "(debug) ")?;
+            output.flush()?;
+        }
+
+        writeln!(output)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    fn debugger(program: &str) -> Debugger<Vec<i64>> {
+        Debugger::new(Machine::new(parse_program(program).unwrap()))
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("break 12"), Ok(Command::Break(Some(12))));
+        assert_eq!(parse_command("step"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("s 4"), Ok(Command::Step(4)));
+        assert_eq!(parse_command("print 3..6"), Ok(Command::Print(3, 6)));
+        assert_eq!(parse_command("print 3"), Ok(Command::Print(3, 4)));
+        assert_eq!(parse_command("print 10..4106"), Ok(Command::Print(10, 4106)));
+        assert_eq!(parse_command("print 0..4294967295"), Err("'0..4294967295' is more than 4096 cells".to_string()));
+        assert_eq!(parse_command("set 4 -7"), Ok(Command::Set(4, -7)));
+        assert_eq!(parse_command("rs 3"), Ok(Command::ReverseStep(3)));
+        assert_eq!(parse_command("last-write 9"), Ok(Command::LastWrite(9)));
+        assert!(parse_command("set 4").is_err());
+        assert_eq!(parse_command("print 18446744073709551615"), Err("'18446744073709551615' is not a valid address".to_string()));
+        assert!(parse_command("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger("3,9,8,9,10,9,4,9,99,-1,8");
+
+        debugger.execute(Command::Break(Some(6)));
+
+        assert_eq!(debugger.execute(Command::Continue), "Waiting for input at 0, use 'input VALUE'");
+
+        debugger.execute(Command::Input(8));
+
+        assert_eq!(debugger.execute(Command::Continue), "Breakpoint at 6");
+        assert_eq!(debugger.execute(Command::Print(9, 11)), "9:\t1\n10:\t8");
+        assert_eq!(debugger.execute(Command::Disasm(2)), "=> 6:\tOUT   [9]\n   8:\tHALT");
+
+        debugger.execute(Command::Set(9, 42));
+
+        assert_eq!(debugger.execute(Command::Step(5)), "Output: 42\nHalted at 8");
+        assert_eq!(debugger.execute(Command::Regs), "ip = 8, rb = 0, status = Halted");
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut debugger = debugger("1,9,10,9,1002,9,2,10,99,3,4");
+
+        debugger.execute(parse_command("watch 10:w").unwrap());
+
+        assert_eq!(debugger.execute(Command::Continue), "Watchpoint: 4:\twrite [10] 4 -> 14\nStopped at 8");
+        assert_eq!(debugger.execute(Command::Continue), "Halted at 8");
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut debugger = debugger("3,13,1,13,13,13,1001,13,1,13,4,13,99,0");
+
+        debugger.execute(Command::Input(5));
+        debugger.execute(Command::Break(Some(6)));
+
+        assert_eq!(debugger.execute(Command::Continue), "Breakpoint at 6");
+        assert_eq!(debugger.execute(Command::Continue), "Output: 11\nHalted at 12");
+        assert_eq!(debugger.execute(Command::LastWrite(13)), "13 last written at cycle 2 by 6: 10 -> 11");
+        assert_eq!(debugger.execute(Command::ReverseContinue), "Breakpoint at 6");
+        assert_eq!(debugger.execute(Command::Print(13, 14)), "13:\t10");
+        assert_eq!(debugger.execute(Command::ReverseStep(5)), "Reached the start of history at 0");
+        assert_eq!(debugger.machine().input(), &VecDeque::from(vec![5]));
+        assert_eq!(debugger.execute(Command::LastWrite(13)), "No recorded write to 13");
+
+        debugger.execute(Command::Set(13, 7));
+        debugger.set_history_limit(Some(1));
+        debugger.execute(Command::Step(1));
+
+        assert_eq!(debugger.machine().history().len(), 1);
+        assert_eq!(debugger.execute(Command::Print(13, 14)), "13:\t5");
+        assert_eq!(debugger.execute(Command::ReverseStep(1)), "Stopped at 0");
+        assert_eq!(debugger.execute(Command::Print(13, 14)), "13:\t7");
+        assert_eq!(debugger.execute(Command::ReverseStep(1)), "Reached the start of history at 0");
+
+        debugger.set_history_limit(None);
+        debugger.execute(Command::Set(13, 8));
+
+        assert_eq!(debugger.execute(Command::ReverseStep(1)), "Stopped at 0");
+        assert_eq!(debugger.execute(Command::Print(13, 14)), "13:\t7");
+    }
+
+    #[test]
+    fn test_failure() {
+        let mut debugger = debugger("1101,1,1,5,42,0");
+
+        assert_eq!(debugger.execute(Command::Continue), "Failed at 4: ip 4 (42): unknown opcode 42");
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut debugger = debugger("1101,2,3,5,99,0");
+        let mut output = vec![];
+
+        debugger.repl("step\n\nprint 5\nquit\n".as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Stopped at 4"));
+        assert!(output.contains("Halted at 4"));
+        assert!(output.contains("5:\t5"));
+    }
+}
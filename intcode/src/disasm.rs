@@ -0,0 +1,213 @@
+use std::fmt;
+
+use super::opcode::{mnemonic, parse_opcode, write_param, OpcodeMode};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: i64,
+    pub params: Vec<(OpcodeMode, i64)>,
+}
+
+impl Instruction {
+    // How many words the instruction takes up
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    // Where execution can go next, as far as we can tell without running anything
+    pub fn successors(&self) -> Vec<usize> {
+        let next = self.address + self.size();
+
+        match self.opcode {
+            5 | 6 => {
+                let (cond_mode, cond) = self.params[0];
+                let (target_mode, target) = self.params[1];
+                let mut result = vec![];
+
+                // A jump on an immediate condition either always or never happens
+                let always = cond_mode == OpcodeMode::Immediate && ((cond != 0) == (self.opcode == 5));
+                let never = cond_mode == OpcodeMode::Immediate && !always;
+
+                if !never && target_mode == OpcodeMode::Immediate && target >= 0 {
+                    result.push(target as usize);
+                }
+
+                if !always {
+                    result.push(next);
+                }
+
+                result
+            },
+            99 => vec![],
+            _ => vec![next],
+        }
+    }
+}
+
+pub fn format_operand(mode: OpcodeMode, value: i64) -> String {
+    match mode {
+        OpcodeMode::Position => format!("[{}]", value),
+        OpcodeMode::Immediate => format!("#{}", value),
+        OpcodeMode::Relative if value < 0 => format!("rb{}", value),
+        OpcodeMode::Relative => format!("rb+{}", value),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = mnemonic(self.opcode).unwrap_or("???");
+        let out_idx = write_param(self.opcode);
+
+        let inputs: Vec<String> = self.params.iter().enumerate()
+            .filter(|&(idx, _)| Some(idx) != out_idx || self.params.len() == 1)
+            .map(|(_, &(mode, value))| format_operand(mode, value))
+            .collect();
+
+        if inputs.is_empty() {
+            return write!(f, "{}", name);
+        }
+
+        write!(f, "{:<6}{}", name, inputs.join(", "))?;
+
+        // Mirror the trace by putting the destination after an arrow
+        if let Some(idx) = out_idx {
+            if self.params.len() > 1 {
+                let (mode, value) = self.params[idx];
+                write!(f, "\t-> {}", format_operand(mode, value))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Decode the instruction at an address, if there's a valid one there
+pub fn decode(program: &[i64], address: usize) -> Option<Instruction> {
+    let (opcode, modes) = parse_opcode(*program.get(address)?).ok()?;
+    let mut params = vec![];
+
+    for (idx, mode) in modes.into_iter().enumerate() {
+        params.push((mode, *program.get(address + 1 + idx)?));
+    }
+
+    Some(Instruction {address, opcode, params})
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: usize, value: i64 },
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Code(instruction) => write!(f, "{}:\t{}", instruction.address, instruction),
+            Line::Data {address, value} => write!(f, "{}:\tDATA  {}", address, value),
+        }
+    }
+}
+
+// Walk the program from address 0 following fall-throughs and immediate jump targets.
+// Anything we never reach is listed as data
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    disassemble_from(program, &[0])
+}
+
+// As disassemble(), but also walking from other known entry points, such as addresses
+// seen executing. Earlier roots win where instructions would overlap
+pub fn disassemble_from(program: &[i64], roots: &[usize]) -> Vec<Line> {
+    let mut claimed = vec![false; program.len()];
+    let mut starts: Vec<Option<Instruction>> = vec![None; program.len()];
+    let mut roots = roots.iter();
+    let mut pending = vec![];
+
+    while let Some(address) = pending.pop().or_else(|| roots.next().cloned()) {
+        if address >= program.len() || claimed[address] {
+            continue;
+        }
+
+        let instruction = match decode(program, address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+
+        // Don't decode instructions overlapping ones we already have
+        if claimed[address..address + instruction.size()].iter().any(|&c| c) {
+            continue;
+        }
+
+        for flag in &mut claimed[address..address + instruction.size()] {
+            *flag = true;
+        }
+
+        pending.extend(instruction.successors());
+        starts[address] = Some(instruction);
+    }
+
+    let mut lines = vec![];
+    let mut address = 0;
+
+    while address < program.len() {
+        match starts[address].take() {
+            Some(instruction) => {
+                address += instruction.size();
+                lines.push(Line::Code(instruction));
+            },
+            None => {
+                lines.push(Line::Data {address, value: program[address]});
+                address += 1;
+            },
+        }
+    }
+
+    lines
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    fn listing(program: &str) -> Vec<String> {
+        disassemble(&parse_program(program).unwrap()).iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(listing("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9"), vec![
+            "0:\tIN    [12]",
+            "2:\tJMPF  [12], [15]",
+            "5:\tADD   [13], [14]\t-> [13]",
+            "9:\tOUT   [13]",
+            "11:\tHALT",
+            "12:\tDATA  -1",
+            "13:\tDATA  0",
+            "14:\tDATA  1",
+            "15:\tDATA  9",
+        ]);
+    }
+
+    #[test]
+    fn test_roots() {
+        // The jump always goes through a pointer, so only the extra root finds the HALT
+        let program = parse_program("106,0,5,104,1,99").unwrap();
+        let lines: Vec<String> = disassemble_from(&program, &[0, 5]).iter().map(|line| line.to_string()).collect();
+
+        assert_eq!(lines, vec!["0:\tJMPF  #0, [5]", "3:\tDATA  104", "4:\tDATA  1", "5:\tHALT"]);
+    }
+
+    #[test]
+    fn test_jumps() {
+        // The unconditional jump skips the 99 at address 3, so it's data
+        assert_eq!(listing("1105,1,4,99,104,7,21101,1,2,-3,99"), vec![
+            "0:\tJMPT  #1, #4",
+            "3:\tDATA  99",
+            "4:\tOUT   #7",
+            "6:\tADD   #1, #2\t-> rb-3",
+            "10:\tHALT",
+        ]);
+    }
+}
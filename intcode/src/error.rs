@@ -0,0 +1,78 @@
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+// Everything that can stop a program short of halting. Each carries the instruction
+// pointer and the raw instruction being executed at the time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IntcodeError {
+    UnknownOpcode { ip: usize, instruction: i64 },
+    InvalidMode { ip: usize, instruction: i64, mode: i64 },
+    WriteInImmediateMode { ip: usize, instruction: i64 },
+    AddressOutOfRange { ip: usize, instruction: i64, address: usize, limit: usize },
+    NegativeAddress { ip: usize, instruction: i64, address: i64 },
+    InputExhausted { ip: usize, instruction: i64 },
+    StepLimitExceeded { ip: usize, instruction: i64, limit: u64 },
+    TimedOut { ip: usize, instruction: i64, timeout: Duration },
+    // The whole machine state repeated without any I/O in between
+    InfiniteLoop { ip: usize, instruction: i64 },
+    // Arithmetic, or working out a relative address, went past the range of an i64
+    Overflow { ip: usize, instruction: i64 },
+}
+
+impl IntcodeError {
+    pub fn ip(&self) -> usize {
+        match *self {
+            IntcodeError::UnknownOpcode {ip, ..} => ip,
+            IntcodeError::InvalidMode {ip, ..} => ip,
+            IntcodeError::WriteInImmediateMode {ip, ..} => ip,
+            IntcodeError::AddressOutOfRange {ip, ..} => ip,
+            IntcodeError::NegativeAddress {ip, ..} => ip,
+            IntcodeError::InputExhausted {ip, ..} => ip,
+            IntcodeError::StepLimitExceeded {ip, ..} => ip,
+            IntcodeError::TimedOut {ip, ..} => ip,
+            IntcodeError::InfiniteLoop {ip, ..} => ip,
+            IntcodeError::Overflow {ip, ..} => ip,
+        }
+    }
+
+    pub fn instruction(&self) -> i64 {
+        match *self {
+            IntcodeError::UnknownOpcode {instruction, ..} => instruction,
+            IntcodeError::InvalidMode {instruction, ..} => instruction,
+            IntcodeError::WriteInImmediateMode {instruction, ..} => instruction,
+            IntcodeError::AddressOutOfRange {instruction, ..} => instruction,
+            IntcodeError::NegativeAddress {instruction, ..} => instruction,
+            IntcodeError::InputExhausted {instruction, ..} => instruction,
+            IntcodeError::StepLimitExceeded {instruction, ..} => instruction,
+            IntcodeError::TimedOut {instruction, ..} => instruction,
+            IntcodeError::InfiniteLoop {instruction, ..} => instruction,
+            IntcodeError::Overflow {instruction, ..} => instruction,
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ip {} ({}): ", self.ip(), self.instruction())?;
+
+        match *self {
+            IntcodeError::UnknownOpcode {instruction, ..} => {
+                write!(f, "unknown opcode {}", instruction % 100)
+            },
+            IntcodeError::InvalidMode {mode, ..} => write!(f, "invalid parameter mode {}", mode),
+            IntcodeError::WriteInImmediateMode {..} => write!(f, "write to a parameter in immediate mode"),
+            IntcodeError::AddressOutOfRange {address, limit, ..} => {
+                write!(f, "address {} is beyond the memory limit of {}", address, limit)
+            },
+            IntcodeError::NegativeAddress {address, ..} => write!(f, "negative address {}", address),
+            IntcodeError::InputExhausted {..} => write!(f, "input requested but none is available"),
+            IntcodeError::StepLimitExceeded {limit, ..} => write!(f, "step limit of {} exceeded", limit),
+            IntcodeError::TimedOut {timeout, ..} => write!(f, "timed out after {:?}", timeout),
+            IntcodeError::InfiniteLoop {..} => write!(f, "stuck in a loop with no input or output"),
+            IntcodeError::Overflow {..} => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl error::Error for IntcodeError {}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+use super::machine::Status;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// Everything needed to undo one executed instruction: the registers from before it ran
+// and the writes it made, in order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub cycle: u64,
+    pub ip: usize,
+    pub relative_base: i64,
+    pub status: Status,
+    pub writes: Vec<MemoryWrite>,
+    // The input value consumed, if any, so it can be handed back
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+}
+
+impl Step {
+    pub fn new(cycle: u64, ip: usize, relative_base: i64, status: Status) -> Step {
+        Step {cycle, ip, relative_base, status, writes: vec![], input: None, output: None}
+    }
+
+    pub fn write_to(&self, address: usize) -> Option<&MemoryWrite> {
+        self.writes.iter().rev().find(|write| write.address == address)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cycle {} at {}", self.cycle, self.ip)?;
+
+        for write in &self.writes {
+            write!(f, ", [{}] {} -> {}", write.address, write.old, write.new)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, Sender};
+
+// Parse input values separated by commas, whitespace or both, as typed on a command line
+// or listed one per line in a file
+pub fn parse_values(text: &str) -> Result<Vec<i64>, ParseIntError> {
+    text.split(|c: char| c == ',' || c.is_whitespace()).filter(|value| !value.is_empty()).map(i64::from_str).collect()
+}
+
+// A line of text as an ASCII program reads it, one code per character ending with 10
+pub fn encode_ascii(line: &str) -> Vec<i64> {
+    line.chars().map(|c| c as i64).chain(Some(10)).collect()
+}
+
+// The character an ASCII program means by an output, or None for values outside ASCII,
+// which such programs use for numeric answers
+pub fn ascii_char(value: i64) -> Option<char> {
+    match value {
+        0..=127 => Some(value as u8 as char),
+        _ => None,
+    }
+}
+
+pub trait Input {
+    // Produce the next input value, or None if there isn't one available
+    fn read(&mut self) -> Option<i64>;
+
+    // Values waiting to be read, for devices that buffer them. Snapshots save these
+    // and put them back with set_pending
+    fn pending(&self) -> Vec<i64> {
+        vec![]
+    }
+
+    fn set_pending(&mut self, _values: &[i64]) {}
+}
+
+pub trait Output {
+    fn write(&mut self, value: i64);
+
+    // Values written so far, for devices that keep them
+    fn written(&self) -> Vec<i64> {
+        vec![]
+    }
+
+    fn set_written(&mut self, _values: &[i64]) {}
+}
+
+impl Input for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+
+    fn pending(&self) -> Vec<i64> {
+        self.iter().cloned().collect()
+    }
+
+    fn set_pending(&mut self, values: &[i64]) {
+        *self = values.iter().cloned().collect();
+    }
+}
+
+impl Output for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value);
+    }
+
+    fn written(&self) -> Vec<i64> {
+        self.iter().cloned().collect()
+    }
+
+    fn set_written(&mut self, values: &[i64]) {
+        *self = values.iter().cloned().collect();
+    }
+}
+
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+
+    fn written(&self) -> Vec<i64> {
+        self.clone()
+    }
+
+    fn set_written(&mut self, values: &[i64]) {
+        *self = values.to_vec();
+    }
+}
+
+// Discard anything written
+impl Output for () {
+    fn write(&mut self, _value: i64) {}
+}
+
+// Block until a value arrives, with a hung-up sender meaning there's no more input
+impl Input for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+// A receiver that has gone away just means nobody is listening any more
+impl Output for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+// Read a value from the next line, giving None at end of file (or on a read error) and
+// an error for a line that isn't a number
+fn read_number<R: BufRead>(reader: &mut R) -> Result<Option<i64>, String> {
+    let mut line = String::new();
+
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => Ok(None),
+        Ok(_) => i64::from_str(line.trim()).map(Some).map_err(|_| format!("'{}' is not a number", line.trim())),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StdinInput {
+    error: Option<String>,
+}
+
+impl StdinInput {
+    pub fn new() -> StdinInput {
+        StdinInput::default()
+    }
+
+    // Why the input stopped, if it was a line that isn't a number rather than end of
+    // file, since either way the machine just sees no more input
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl Input for StdinInput {
+    // Read one value per line, with end of file or a bad line meaning no more input
+    fn read(&mut self) -> Option<i64> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        match read_number(&mut io::stdin().lock()) {
+            Ok(value) => value,
+            Err(error) => {
+                self.error = Some(error);
+                None
+            },
+        }
+    }
+}
+
+// Reads a line of text at a time, handing it out as ASCII codes
+#[derive(Debug)]
+pub struct AsciiInput<R: BufRead> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> AsciiInput<R> {
+        AsciiInput {reader, pending: VecDeque::new()}
+    }
+}
+
+impl<R: BufRead> Input for AsciiInput<R> {
+    // End of file (or a read error) means no more input
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => self.pending.extend(encode_ascii(line.trim_end_matches(&['\r', '\n'][..]))),
+            }
+        }
+
+        self.pending.pop_front()
+    }
+
+    fn pending(&self) -> Vec<i64> {
+        self.pending.iter().cloned().collect()
+    }
+
+    fn set_pending(&mut self, values: &[i64]) {
+        self.pending = values.iter().cloned().collect();
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StdoutOutput;
+
+impl StdoutOutput {
+    pub fn new() -> StdoutOutput {
+        StdoutOutput
+    }
+}
+
+impl Output for StdoutOutput {
+    fn write(&mut self, value: i64) {
+        println!("{}", value);
+    }
+}
+
+pub struct FnInput<F: FnMut() -> Option<i64>>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Input for FnInput<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+pub struct FnOutput<F: FnMut(i64)>(pub F);
+
+impl<F: FnMut(i64)> Output for FnOutput<F> {
+    fn write(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_queue() {
+        let mut input: VecDeque<i64> = vec![1, 2].into_iter().collect();
+
+        assert_eq!(input.read(), Some(1));
+        assert_eq!(input.read(), Some(2));
+        assert_eq!(input.read(), None);
+    }
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(parse_values("1,5, 7\n-2\n\n"), Ok(vec![1, 5, 7, -2]));
+        assert_eq!(parse_values(""), Ok(vec![]));
+        assert!(parse_values("1,x").is_err());
+    }
+
+    #[test]
+    fn test_numbers() {
+        let mut reader = " 12\n-3\nabc\n".as_bytes();
+
+        assert_eq!(read_number(&mut reader), Ok(Some(12)));
+        assert_eq!(read_number(&mut reader), Ok(Some(-3)));
+        assert_eq!(read_number(&mut reader), Err("'abc' is not a number".to_string()));
+        assert_eq!(read_number(&mut reader), Ok(None));
+        assert_eq!(StdinInput::new().error(), None);
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut input = AsciiInput::new("hi\r\n\nno".as_bytes());
+        let mut codes = vec![];
+
+        while let Some(code) = input.read() {
+            codes.push(code);
+        }
+
+        assert_eq!(codes, vec![104, 105, 10, 10, 110, 111, 10]);
+        assert_eq!(encode_ascii("A"), vec![65, 10]);
+        assert_eq!(ascii_char(10), Some('\n'));
+        assert_eq!(ascii_char(128), None);
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut next = 0;
+        let mut input = FnInput(|| { next += 1; Some(next) });
+
+        assert_eq!(input.read(), Some(1));
+        assert_eq!(input.read(), Some(2));
+
+        let mut seen = vec![];
+        {
+            let mut output = FnOutput(|value| seen.push(value));
+            output.write(7);
+        }
+
+        assert_eq!(seen, vec![7]);
+    }
+
+    #[test]
+    fn test_channel() {
+        let (mut tx, mut rx) = channel();
+
+        tx.write(5);
+        drop(tx);
+
+        assert_eq!(rx.read(), Some(5));
+        assert_eq!(rx.read(), None);
+    }
+}
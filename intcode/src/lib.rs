@@ -0,0 +1,20 @@
+pub mod asm;
+pub mod circuit;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod history;
+pub mod io;
+pub mod machine;
+pub mod memory;
+pub mod network;
+pub mod opcode;
+pub mod profile;
+pub mod report;
+pub mod search;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
+pub mod watch;
+pub mod watchdog;
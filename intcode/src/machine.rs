@@ -0,0 +1,964 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::error::IntcodeError;
+use super::history::{MemoryWrite, Step};
+use super::io::{Input, Output};
+use super::memory::{Memory, MemoryError};
+use super::opcode::{parse_opcode, OpcodeError, OpcodeMode};
+use super::snapshot::Snapshot;
+use super::trace::{TraceEvent, Tracer};
+use super::watch::{Access, WatchEvent, Watchpoint};
+use super::watchdog::LoopDetector;
+
+// Reading the clock every step would be slow, so the timeout is checked this often
+const TIMEOUT_INTERVAL: u64 = 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Halted,
+    NeedsInput,
+    Output(i64),
+    // Stopped early at the caller's request, e.g. by a watchpoint
+    Break,
+}
+
+#[derive(Debug)]
+pub struct Machine<I = VecDeque<i64>, O = Vec<i64>, T = ()> {
+    memory: Memory,
+    ip: usize,
+    relative_base: i64,
+    status: Status,
+    error: Option<IntcodeError>,
+    resumable: bool,
+    instruction: i64,
+    cycles: u64,
+    recording: bool,
+    history_limit: Option<usize>,
+    history: VecDeque<Step>,
+    step_limit: Option<u64>,
+    timeout: Option<(Duration, Instant)>,
+    loop_detector: Option<LoopDetector>,
+    // The undo record for the instruction being executed, while recording
+    current: Option<Step>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchEvent>,
+    input: I,
+    output: O,
+    tracer: T,
+}
+
+impl Machine {
+    // A headless machine reading from a queue and collecting its outputs in a vector
+    pub fn new(program: Vec<i64>) -> Machine {
+        Machine::with_io(program, VecDeque::new(), vec![])
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Machine {
+        let mut machine = Machine::new(vec![]);
+        machine.restore(snapshot);
+        machine
+    }
+}
+
+impl<O: Output, T: Tracer> Machine<VecDeque<i64>, O, T> {
+    // Queue up a value for the next input instruction
+    pub fn feed(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+}
+
+impl<I: Input, O: Output> Machine<I, O> {
+    pub fn with_io(program: Vec<i64>, input: I, output: O) -> Machine<I, O> {
+        Machine {
+            memory: Memory::new(program),
+            ip: 0,
+            relative_base: 0,
+            status: Status::Running,
+            error: None,
+            resumable: true,
+            instruction: 0,
+            cycles: 0,
+            recording: false,
+            history_limit: None,
+            history: VecDeque::new(),
+            step_limit: None,
+            timeout: None,
+            loop_detector: None,
+            current: None,
+            watchpoints: vec![],
+            watch_hits: vec![],
+            input,
+            output,
+            tracer: (),
+        }
+    }
+}
+
+impl<I: Input, O: Output, T: Tracer> Machine<I, O, T> {
+    // Swap in a tracer to be told about every instruction executed
+    pub fn with_tracer<U: Tracer>(self, tracer: U) -> Machine<I, O, U> {
+        Machine {
+            memory: self.memory,
+            ip: self.ip,
+            relative_base: self.relative_base,
+            status: self.status,
+            error: self.error,
+            resumable: self.resumable,
+            instruction: self.instruction,
+            cycles: self.cycles,
+            recording: self.recording,
+            history_limit: self.history_limit,
+            history: self.history,
+            step_limit: self.step_limit,
+            timeout: self.timeout,
+            loop_detector: self.loop_detector,
+            current: self.current,
+            watchpoints: self.watchpoints,
+            watch_hits: self.watch_hits,
+            input: self.input,
+            output: self.output,
+            tracer,
+        }
+    }
+
+    pub fn tracer(&self) -> &T {
+        &self.tracer
+    }
+
+    pub fn tracer_mut(&mut self) -> &mut T {
+        &mut self.tracer
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    // The error that stopped the machine, if any
+    pub fn error(&self) -> Option<IntcodeError> {
+        self.error
+    }
+
+    // By default a machine with no input pauses with Status::NeedsInput so it can be fed
+    // and resumed. A machine that isn't resumable fails with InputExhausted instead
+    pub fn set_resumable(&mut self, resumable: bool) {
+        self.resumable = resumable;
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    // The number of instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Fail with StepLimitExceeded rather than execute more than this many instructions
+    // in total
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    // Fail with TimedOut once this much time has passed from now, including any time
+    // spent waiting for input
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout.map(|timeout| (timeout, Instant::now() + timeout));
+    }
+
+    // Fail with InfiniteLoop if the machine's state repeats without any I/O. This costs
+    // the odd copy of memory, so it's off by default
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = if enabled { Some(LoopDetector::new()) } else { None };
+    }
+
+    // While recording, every executed instruction leaves an undo record so the machine
+    // can be stepped backwards
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    // Only keep the most recent records, to bound memory use on long runs
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history_limit = limit;
+
+        if let Some(limit) = limit {
+            while self.history.len() > limit {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    pub fn history(&self) -> &VecDeque<Step> {
+        &self.history
+    }
+
+    // Undo the most recently recorded instruction, returning its record. Outputs can't
+    // be taken back, and any input it consumed is left for the caller to return
+    pub fn reverse_step(&mut self) -> Option<Step> {
+        let step = self.history.pop_back()?;
+
+        for write in step.writes.iter().rev() {
+            // The address was already written once, so this can't be out of range
+            let _ = self.memory.set(write.address, write.old);
+        }
+
+        self.ip = step.ip;
+        self.relative_base = step.relative_base;
+        self.status = step.status;
+        self.error = None;
+        self.cycles = step.cycle;
+
+        Some(step)
+    }
+
+    // Write to memory from outside the program, e.g. from a debugger. While recording the
+    // write goes into the history like an instruction's, so reverse stepping undoes it
+    pub fn poke(&mut self, address: usize, value: i64) -> Result<(), MemoryError> {
+        let old = self.memory.get(address)?;
+        self.memory.set(address, value)?;
+
+        if self.recording {
+            let mut step = Step::new(self.cycles, self.ip, self.relative_base, self.status);
+            step.writes.push(MemoryWrite {address, old, new: value});
+            self.record(step);
+        }
+
+        Ok(())
+    }
+
+    // The most recent recorded write to an address
+    pub fn last_write(&self, address: usize) -> Option<(&Step, &MemoryWrite)> {
+        self.history.iter().rev().find_map(|step| step.write_to(address).map(|write| (step, write)))
+    }
+
+    // Capture the machine's state, including any input and output its devices buffer
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.cells().to_vec(),
+            sparse: self.memory.sparse(),
+            memory_limit: self.memory.limit(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            status: self.status,
+            cycles: self.cycles,
+            input: self.input.pending(),
+            output: self.output.written(),
+        }
+    }
+
+    // Put the machine back in a saved state. Any error is cleared and the recorded
+    // history dropped, as it no longer applies
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let mut memory = Memory::new(snapshot.memory.clone());
+        memory.set_limit(snapshot.memory_limit);
+
+        // Snapshot::parse turns away sparse cells past the limit, so none are lost here
+        for &(address, value) in &snapshot.sparse {
+            let _ = memory.set(address, value);
+        }
+
+        self.memory = memory;
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.status = snapshot.status;
+        self.cycles = snapshot.cycles;
+        self.error = None;
+        self.history.clear();
+
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+        self.input.set_pending(&snapshot.input);
+        self.output.set_written(&snapshot.output);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Remove any watchpoints covering an address
+    pub fn remove_watchpoint(&mut self, address: usize) {
+        self.watchpoints.retain(|watchpoint| address < watchpoint.start || address >= watchpoint.end);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Watched reads and writes made by the most recent step
+    pub fn watch_hits(&self) -> &[WatchEvent] {
+        &self.watch_hits
+    }
+
+    fn watched(&self, address: usize, access: Access) -> bool {
+        self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, access))
+    }
+
+    // Attach the current ip and instruction to a memory error
+    fn memory_error(&self, error: MemoryError) -> IntcodeError {
+        match error {
+            MemoryError::OutOfRange {address, limit} => IntcodeError::AddressOutOfRange {
+                ip: self.ip,
+                instruction: self.instruction,
+                address,
+                limit,
+            },
+        }
+    }
+
+    fn fetch(&self, address: usize) -> Result<i64, IntcodeError> {
+        self.memory.get(address).map_err(|e| self.memory_error(e))
+    }
+
+    // Read an operand's value from memory, noting it if the address is being watched
+    fn read(&mut self, address: usize) -> Result<i64, IntcodeError> {
+        let value = self.fetch(address)?;
+
+        if self.watched(address, Access::Read) {
+            self.watch_hits.push(WatchEvent {ip: self.ip, address, access: Access::Read, old: value, new: value});
+        }
+
+        Ok(value)
+    }
+
+    fn write(&mut self, address: usize, value: i64) -> Result<(), IntcodeError> {
+        if self.watched(address, Access::Write) {
+            let old = self.fetch(address)?;
+            self.watch_hits.push(WatchEvent {ip: self.ip, address, access: Access::Write, old, new: value});
+        }
+
+        if let Some(detector) = &mut self.loop_detector {
+            detector.note_write();
+        }
+
+        if self.current.is_some() {
+            let old = self.fetch(address)?;
+
+            if let Some(step) = &mut self.current {
+                step.writes.push(MemoryWrite {address, old, new: value});
+            }
+        }
+
+        self.memory.set(address, value).map_err(|e| self.memory_error(e))
+    }
+
+    // Convert a value into a memory address, refusing to wrap negative values around
+    fn address(&self, value: i64) -> Result<usize, IntcodeError> {
+        if value < 0 {
+            return Err(IntcodeError::NegativeAddress {ip: self.ip, instruction: self.instruction, address: value});
+        }
+
+        Ok(value as usize)
+    }
+
+    // Turn the None from checked arithmetic into an error
+    fn checked(&self, value: Option<i64>) -> Result<i64, IntcodeError> {
+        value.ok_or(IntcodeError::Overflow {ip: self.ip, instruction: self.instruction})
+    }
+
+    fn get_param(&mut self, ip: usize, mode: OpcodeMode) -> Result<i64, IntcodeError> {
+        let value = self.fetch(ip)?;
+
+        match mode {
+            OpcodeMode::Position => self.read(self.address(value)?),
+            OpcodeMode::Immediate => Ok(value),
+            OpcodeMode::Relative => self.read(self.address(self.checked(self.relative_base.checked_add(value))?)?),
+        }
+    }
+
+    fn get_out_ptr(&self, ip: usize, mode: OpcodeMode) -> Result<usize, IntcodeError> {
+        let value = self.fetch(ip)?;
+
+        match mode {
+            OpcodeMode::Position => self.address(value),
+            OpcodeMode::Immediate => Err(IntcodeError::WriteInImmediateMode {ip: self.ip, instruction: self.instruction}),
+            OpcodeMode::Relative => self.address(self.checked(self.relative_base.checked_add(value))?),
+        }
+    }
+
+    // Execute the instruction at the instruction pointer and return the resulting status,
+    // which is Output(value) for the step that produced a value. Once a machine has
+    // failed every later step returns the same error
+    pub fn step(&mut self) -> Result<Status, IntcodeError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        match self.status {
+            Status::Halted => return Ok(self.status),
+            _ => self.status = Status::Running,
+        }
+
+        self.watch_hits.clear();
+
+        // Running off the end of the program is treated like a halt, as it always has been,
+        // though code the program wrote out into sparse memory still runs
+        if !self.memory.is_written(self.ip) {
+            self.status = Status::Halted;
+            return Ok(self.status);
+        }
+
+        if self.recording {
+            self.current = Some(Step::new(self.cycles, self.ip, self.relative_base, self.status));
+        }
+
+        let result = self.check_limits().and_then(|()| self.execute()).and_then(|value| self.check_loop(value));
+
+        // An instruction waiting on input hasn't done anything yet, so there's nothing to undo
+        if self.status != Status::NeedsInput {
+            if result.is_ok() {
+                self.cycles += 1;
+            }
+
+            if let Some(step) = self.current.take() {
+                self.record(step);
+            }
+        }
+
+        self.current = None;
+
+        match result {
+            Ok(Some(value)) => Ok(Status::Output(value)),
+            Ok(None) => Ok(self.status),
+            Err(error) => {
+                self.tracer.fail(&error);
+                self.error = Some(error);
+                Err(error)
+            }
+        }
+    }
+
+    // Stop runaway programs before they execute another instruction
+    fn check_limits(&mut self) -> Result<(), IntcodeError> {
+        let limit_hit = self.step_limit.is_some_and(|limit| self.cycles >= limit);
+        let timed_out = self.cycles.is_multiple_of(TIMEOUT_INTERVAL) && self.timeout.is_some_and(|(_, deadline)| Instant::now() >= deadline);
+
+        if !limit_hit && !timed_out {
+            return Ok(());
+        }
+
+        let ip = self.ip;
+        self.instruction = self.memory.get(ip).unwrap_or(0);
+
+        match (self.step_limit, self.timeout) {
+            (Some(limit), _) if limit_hit => Err(IntcodeError::StepLimitExceeded {ip, instruction: self.instruction, limit}),
+            (_, Some((timeout, _))) => Err(IntcodeError::TimedOut {ip, instruction: self.instruction, timeout}),
+            _ => unreachable!("a limit was hit without being set"),
+        }
+    }
+
+    fn check_loop(&mut self, value: Option<i64>) -> Result<Option<i64>, IntcodeError> {
+        if self.status == Status::Running {
+            if let Some(detector) = &mut self.loop_detector {
+                if detector.check(self.ip, self.relative_base, &self.memory) {
+                    let instruction = self.memory.get(self.ip).unwrap_or(0);
+                    return Err(IntcodeError::InfiniteLoop {ip: self.ip, instruction});
+                }
+            }
+        }
+
+        Ok(value)
+    }
+
+    // Any I/O changes the machine's situation, so looping so far doesn't count
+    fn note_io(&mut self) {
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+    }
+
+    fn record(&mut self, step: Step) {
+        if self.history_limit == Some(0) {
+            return;
+        }
+
+        if Some(self.history.len()) == self.history_limit {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(step);
+    }
+
+    // Report an executed instruction, once its effects have been applied
+    fn trace(&mut self, ip: usize, opcode: i64, modes: &[OpcodeMode], operands: &[i64], write: Option<(usize, i64)>) {
+        if !self.tracer.enabled() {
+            return;
+        }
+
+        self.tracer.trace(&TraceEvent {
+            ip,
+            instruction: self.instruction,
+            opcode,
+            modes,
+            operands,
+            write,
+            relative_base: self.relative_base,
+        });
+    }
+
+    // Returns the value written if the instruction was an output
+    fn execute(&mut self) -> Result<Option<i64>, IntcodeError> {
+        let ip = self.ip;
+        self.instruction = self.fetch(ip)?;
+
+        // Get the opcode and parameter modes
+        let (opcode, modes) = match parse_opcode(self.instruction) {
+            Ok(tuple) => tuple,
+            Err(OpcodeError::UnknownOpcode) => {
+                return Err(IntcodeError::UnknownOpcode {ip, instruction: self.instruction});
+            },
+            Err(OpcodeError::InvalidMode(mode)) => {
+                return Err(IntcodeError::InvalidMode {ip, instruction: self.instruction, mode});
+            },
+        };
+
+        match opcode {
+            1 => {
+                // Add
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+                let out_ptr = self.get_out_ptr(ip + 3, modes[2])?;
+
+                let result = self.checked(lhs_val.checked_add(rhs_val))?;
+
+                self.write(out_ptr, result)?;
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], Some((out_ptr, result)));
+
+                self.ip += 4;
+            },
+            2 => {
+                // Multiply
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+                let out_ptr = self.get_out_ptr(ip + 3, modes[2])?;
+
+                let result = self.checked(lhs_val.checked_mul(rhs_val))?;
+
+                self.write(out_ptr, result)?;
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], Some((out_ptr, result)));
+
+                self.ip += 4;
+            },
+            3 => {
+                // Input
+                let out_ptr = self.get_out_ptr(ip + 1, modes[0])?;
+
+                // Without any input we stay on this instruction so it can be retried later
+                let value = match self.input.read() {
+                    Some(value) => value,
+                    None if self.resumable => {
+                        self.status = Status::NeedsInput;
+                        return Ok(None);
+                    },
+                    None => return Err(IntcodeError::InputExhausted {ip, instruction: self.instruction}),
+                };
+
+                if let Some(step) = &mut self.current {
+                    step.input = Some(value);
+                }
+
+                self.note_io();
+
+                self.write(out_ptr, value)?;
+                self.trace(ip, opcode, &modes, &[], Some((out_ptr, value)));
+
+                self.ip += 2;
+            },
+            4 => {
+                // Output
+                let out_val = self.get_param(ip + 1, modes[0])?;
+
+                if let Some(step) = &mut self.current {
+                    step.output = Some(out_val);
+                }
+
+                self.note_io();
+
+                self.output.write(out_val);
+                self.trace(ip, opcode, &modes, &[out_val], None);
+
+                self.ip += 2;
+
+                return Ok(Some(out_val));
+            },
+            5 => {
+                // Jump if true
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+
+                // Check the target before tracing, so a jump that fails is never traced
+                let next = if lhs_val != 0 { self.address(rhs_val)? } else { ip + 3 };
+
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], None);
+                self.ip = next;
+            },
+            6 => {
+                // Jump if false
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+
+                let next = if lhs_val == 0 { self.address(rhs_val)? } else { ip + 3 };
+
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], None);
+                self.ip = next;
+            },
+            7 => {
+                // Less than
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+                let out_ptr = self.get_out_ptr(ip + 3, modes[2])?;
+
+                let result = (lhs_val < rhs_val) as i64;
+
+                self.write(out_ptr, result)?;
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], Some((out_ptr, result)));
+
+                self.ip += 4;
+            },
+            8 => {
+                // Equals
+                let lhs_val = self.get_param(ip + 1, modes[0])?;
+                let rhs_val = self.get_param(ip + 2, modes[1])?;
+                let out_ptr = self.get_out_ptr(ip + 3, modes[2])?;
+
+                let result = (lhs_val == rhs_val) as i64;
+
+                self.write(out_ptr, result)?;
+                self.trace(ip, opcode, &modes, &[lhs_val, rhs_val], Some((out_ptr, result)));
+
+                self.ip += 4;
+            },
+            9 => {
+                // Adjust relative base
+                let offset = self.get_param(ip + 1, modes[0])?;
+
+                self.relative_base = self.checked(self.relative_base.checked_add(offset))?;
+                self.trace(ip, opcode, &modes, &[offset], None);
+
+                self.ip += 2;
+            },
+            99 => {
+                // Halt
+                self.status = Status::Halted;
+                self.trace(ip, opcode, &modes, &[], None);
+            },
+            _ => unreachable!("parse_opcode accepted opcode {}", opcode),
+        }
+
+        Ok(None)
+    }
+
+    // Step until the machine produces an output, needs input, halts or fails. Calling
+    // this again resumes from where it left off
+    pub fn run(&mut self) -> Result<Status, IntcodeError> {
+        loop {
+            match self.step()? {
+                Status::Running => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+
+    // Like run(), but calling on_hit with each watchpoint hit. If on_hit returns false
+    // the machine stops with Status::Break
+    pub fn run_watched<F: FnMut(&WatchEvent) -> bool>(&mut self, mut on_hit: F) -> Result<Status, IntcodeError> {
+        loop {
+            let status = self.step()?;
+            let mut keep_going = true;
+
+            for hit in &self.watch_hits {
+                keep_going &= on_hit(hit);
+            }
+
+            match status {
+                Status::Running if keep_going => continue,
+                Status::Running => return Ok(Status::Break),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    // Like run(), but keep going past outputs
+    pub fn run_until_blocked(&mut self) -> Result<Status, IntcodeError> {
+        loop {
+            match self.run()? {
+                Status::Output(_) => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+    use crate::history::MemoryWrite;
+    use crate::profile::Profiler;
+    use crate::trace::{TraceFormat, TraceWriter};
+
+    #[test]
+    fn test_get_param() {
+        let mut machine = Machine::new(parse_program("1002,4,3,4,33").unwrap());
+
+        assert_eq!(machine.get_param(1, OpcodeMode::Position), Ok(33));
+        assert_eq!(machine.get_param(1, OpcodeMode::Immediate), Ok(4));
+    }
+
+    #[test]
+    fn test_run() {
+        let mut machine = Machine::new(parse_program("1002,4,3,4,33").unwrap());
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.memory().cells(), &[1002, 4, 3, 4, 99]);
+        assert_eq!(machine.ip(), 4);
+    }
+
+    #[test]
+    fn test_io() {
+        let program = parse_program("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+
+        let mut machine = Machine::new(program.clone());
+        machine.feed(8);
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.output(), &vec![1]);
+
+        let mut machine = Machine::new(program);
+
+        assert_eq!(machine.run(), Ok(Status::NeedsInput));
+        assert_eq!(machine.run(), Ok(Status::NeedsInput));
+        assert_eq!(machine.ip(), 0);
+
+        machine.feed(7);
+
+        assert_eq!(machine.run(), Ok(Status::Output(0)));
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.output(), &vec![0]);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut machine = Machine::new(parse_program("1,9,10,9,1002,9,2,10,99,3,4").unwrap());
+        machine.add_watchpoint(Watchpoint {start: 10, end: 11, on_read: false, on_write: true});
+
+        let mut hits = vec![];
+
+        assert_eq!(machine.run_watched(|hit| { hits.push(*hit); false }), Ok(Status::Break));
+        assert_eq!(machine.ip(), 8);
+        assert_eq!(hits, vec![WatchEvent {ip: 4, address: 10, access: Access::Write, old: 4, new: 14}]);
+
+        machine.remove_watchpoint(10);
+        machine.add_watchpoint(Watchpoint::new(9, 10));
+
+        assert_eq!(machine.run_watched(|_| true), Ok(Status::Halted));
+        assert!(machine.watch_hits().is_empty());
+    }
+
+    #[test]
+    fn test_relative_mode() {
+        let mut machine = Machine::new(parse_program("109,6,21101,2,3,-1,99").unwrap());
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.relative_base(), 6);
+        assert_eq!(machine.memory().cells(), &[109, 6, 21101, 2, 3, 5, 99]);
+    }
+
+    #[test]
+    fn test_memory_growth() {
+        let mut machine = Machine::new(parse_program("1101,2,3,100,99").unwrap());
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.memory().get(100), Ok(5));
+
+        let mut machine = Machine::new(parse_program("1101,2,3,100,99").unwrap());
+        machine.memory_mut().set_limit(50);
+
+        let error = IntcodeError::AddressOutOfRange {ip: 0, instruction: 1101, address: 100, limit: 50};
+
+        assert_eq!(machine.run(), Err(error));
+        assert_eq!(machine.error(), Some(error));
+
+        // Write "104,7,99" out into sparse memory and jump to it
+        let sparse = "1101,104,0,2000000,1101,7,0,2000001,1101,99,0,2000002,1105,1,2000000";
+        let mut machine = Machine::new(parse_program(sparse).unwrap());
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.output().written(), vec![7]);
+        assert_eq!(machine.ip(), 2000002);
+
+        // Jumping to a sparse address that was never written still runs off the end
+        let mut machine = Machine::new(parse_program("1105,1,2000000").unwrap());
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.cycles(), 1);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut machine = Machine::new(parse_program("3,9,109,2,1201,7,5,9,99,0").unwrap())
+            .with_tracer(TraceWriter::new(TraceFormat::Text, vec![]));
+
+        machine.feed(4);
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(String::from_utf8(machine.tracer().writer().clone()).unwrap(), "\
+            0:\tIN    9\n\
+            2:\tARB   2\n\
+            4:\tADD   4, 5\t-> 9\n\
+            8:\tHALT\n");
+
+        // A jump to a negative address fails without being traced
+        let mut machine = Machine::new(parse_program("1105,1,-1").unwrap()).with_tracer(Profiler::new());
+
+        assert!(machine.run().is_err());
+        assert_eq!(machine.tracer().cycles(), 0);
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let mut machine = Machine::new(parse_program("3,11,1,11,11,11,4,11,109,5,99,0").unwrap());
+        machine.set_recording(true);
+        machine.feed(4);
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.cycles(), 5);
+        assert_eq!(machine.memory().get(11), Ok(8));
+
+        let (step, write) = machine.last_write(11).unwrap();
+
+        assert_eq!((step.cycle, step.ip), (1, 2));
+        assert_eq!(*write, MemoryWrite {address: 11, old: 4, new: 8});
+
+        // Undo the halt, the ARB and the output, then the add
+        for _ in 0..3 {
+            machine.reverse_step();
+        }
+
+        assert_eq!((machine.ip(), machine.relative_base(), machine.status()), (6, 0, Status::Running));
+
+        let step = machine.reverse_step().unwrap();
+
+        assert_eq!(step.writes, vec![MemoryWrite {address: 11, old: 4, new: 8}]);
+        assert_eq!(machine.ip(), 2);
+        assert_eq!(machine.cycles(), 1);
+        assert_eq!(machine.memory().get(11), Ok(4));
+
+        let step = machine.reverse_step().unwrap();
+
+        assert_eq!(step.input, Some(4));
+        assert_eq!(machine.memory().get(11), Ok(0));
+        assert_eq!(machine.reverse_step(), None);
+
+        // Replaying gives the same result
+        machine.feed(4);
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(machine.output(), &vec![8, 8]);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut machine = Machine::new(parse_program("1101,1,1,0,1101,2,2,0,99").unwrap());
+        machine.set_recording(true);
+        machine.set_history_limit(Some(2));
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.history().len(), 2);
+        assert_eq!(machine.last_write(0).map(|(step, _)| step.ip), Some(4));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        // Jump back to the start forever
+        let spin = parse_program("1105,1,0").unwrap();
+
+        let mut machine = Machine::new(spin.clone());
+        machine.set_step_limit(Some(10));
+
+        assert_eq!(machine.run(), Err(IntcodeError::StepLimitExceeded {ip: 0, instruction: 1105, limit: 10}));
+        assert_eq!(machine.cycles(), 10);
+
+        let mut machine = Machine::new(spin.clone());
+        machine.set_timeout(Some(Duration::from_millis(0)));
+
+        assert_eq!(machine.run(), Err(IntcodeError::TimedOut {ip: 0, instruction: 1105, timeout: Duration::from_millis(0)}));
+
+        let mut machine = Machine::new(spin);
+        machine.set_loop_detection(true);
+
+        assert_eq!(machine.run(), Err(IntcodeError::InfiniteLoop {ip: 0, instruction: 1105}));
+
+        // Counting down to zero revisits the same addresses but never the same state
+        let mut machine = Machine::new(parse_program("1001,8,-1,8,1005,8,0,99,500").unwrap());
+        machine.set_loop_detection(true);
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn test_errors() {
+        let run = |program: &str| Machine::new(parse_program(program).unwrap()).run();
+
+        assert_eq!(run("42"), Err(IntcodeError::UnknownOpcode {ip: 0, instruction: 42}));
+        assert_eq!(run("1301,0,0,0"), Err(IntcodeError::InvalidMode {ip: 0, instruction: 1301, mode: 3}));
+        assert_eq!(run("11101,1,1,1,99"), Err(IntcodeError::WriteInImmediateMode {ip: 0, instruction: 11101}));
+        assert_eq!(run("1,-1,0,0,99"), Err(IntcodeError::NegativeAddress {ip: 0, instruction: 1, address: -1}));
+        assert_eq!(run("1102,4611686018427387904,4,0,99"), Err(IntcodeError::Overflow {ip: 0, instruction: 1102}));
+        assert_eq!(run("1101,9223372036854775807,1,0,99"), Err(IntcodeError::Overflow {ip: 0, instruction: 1101}));
+        assert_eq!(run("109,9223372036854775807,109,1,99"), Err(IntcodeError::Overflow {ip: 2, instruction: 109}));
+        assert_eq!(run("109,9223372036854775807,204,1,99"), Err(IntcodeError::Overflow {ip: 2, instruction: 204}));
+
+        let mut machine = Machine::new(parse_program("3,0,99").unwrap());
+        machine.set_resumable(false);
+
+        let error = IntcodeError::InputExhausted {ip: 0, instruction: 3};
+
+        assert_eq!(machine.run(), Err(error));
+        assert_eq!(machine.step(), Err(error));
+        assert_eq!(error.to_string(), "ip 0 (3): input requested but none is available");
+    }
+
+    #[test]
+    fn test_large_values() {
+        let mut machine = Machine::new(parse_program("1102,34915192,34915192,7,99,0,0,0").unwrap());
+
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.memory().get(7), Ok(1219070632396864));
+    }
+}
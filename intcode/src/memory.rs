@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Addresses below this are stored contiguously, anything above goes in a sparse map
+pub const DENSE_LIMIT: usize = 1 << 20;
+
+// The default hard cap on addresses a program may touch
+pub const DEFAULT_LIMIT: usize = 1 << 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryError {
+    OutOfRange { address: usize, limit: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::OutOfRange { address, limit } => {
+                write!(f, "address {} is beyond the memory limit of {}", address, limit)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Memory {
+    cells: Vec<i64>,
+    sparse: HashMap<usize, i64>,
+    limit: usize,
+}
+
+impl Memory {
+    pub fn new(program: Vec<i64>) -> Memory {
+        Memory {cells: program, sparse: HashMap::new(), limit: DEFAULT_LIMIT}
+    }
+
+    // The contiguous part of memory, i.e. the program plus anything it has grown into
+    pub fn cells(&self) -> &[i64] {
+        &self.cells
+    }
+
+    // Cells written beyond the contiguous part, in address order
+    pub fn sparse(&self) -> Vec<(usize, i64)> {
+        let mut cells: Vec<(usize, i64)> = self.sparse.iter().map(|(&address, &value)| (address, value)).collect();
+        cells.sort();
+        cells
+    }
+
+    // Whether a cell is part of the contiguous memory or was written out in the sparse
+    // part, as opposed to just reading as zero
+    pub fn is_written(&self, address: usize) -> bool {
+        address < self.cells.len() || self.sparse.contains_key(&address)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    fn check(&self, address: usize) -> Result<(), MemoryError> {
+        if address >= self.limit {
+            return Err(MemoryError::OutOfRange {address, limit: self.limit});
+        }
+
+        Ok(())
+    }
+
+    // Read a cell, with anything that has never been written reading as zero
+    pub fn get(&self, address: usize) -> Result<i64, MemoryError> {
+        self.check(address)?;
+
+        if address < self.cells.len() {
+            Ok(self.cells[address])
+        } else {
+            Ok(*self.sparse.get(&address).unwrap_or(&0))
+        }
+    }
+
+    pub fn set(&mut self, address: usize, value: i64) -> Result<(), MemoryError> {
+        self.check(address)?;
+
+        if address < self.cells.len() {
+            self.cells[address] = value;
+        } else if address < DENSE_LIMIT {
+            self.cells.resize(address + 1, 0);
+            self.cells[address] = value;
+        } else {
+            self.sparse.insert(address, value);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_growth() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+
+        assert_eq!(memory.get(10), Ok(0));
+        assert_eq!(memory.cells().len(), 3);
+
+        memory.set(5, 42).unwrap();
+        assert_eq!(memory.cells(), &[1, 2, 3, 0, 0, 42]);
+
+        memory.set(DENSE_LIMIT * 4, 7).unwrap();
+        assert_eq!(memory.get(DENSE_LIMIT * 4), Ok(7));
+        assert_eq!(memory.sparse(), vec![(DENSE_LIMIT * 4, 7)]);
+        assert_eq!(memory.cells().len(), 6);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+        memory.set_limit(8);
+
+        assert_eq!(memory.set(7, 1), Ok(()));
+        assert_eq!(memory.set(8, 1), Err(MemoryError::OutOfRange {address: 8, limit: 8}));
+        assert_eq!(memory.get(100), Err(MemoryError::OutOfRange {address: 100, limit: 8}));
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::error::IntcodeError;
+use super::machine::{Machine, Status};
+
+// Packets sent here go to the monitor rather than a machine
+pub const MONITOR_ADDRESS: i64 = 255;
+
+// What a machine reads when there's nothing waiting for it
+pub const NO_PACKET: i64 = -1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub dest: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    Send(Packet),
+    Stop,
+}
+
+pub trait Monitor {
+    // Called with every packet addressed to the monitor
+    fn receive(&mut self, packet: Packet) -> Action;
+
+    // Called whenever every queue is empty and every machine is waiting for input
+    fn idle(&mut self) -> Action;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NetworkError {
+    Failed(usize, IntcodeError),
+    BadAddress(Packet),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Failed(idx, error) => write!(f, "machine {} failed: {}", idx, error),
+            NetworkError::BadAddress(packet) => write!(f, "no machine at address {}", packet.dest),
+        }
+    }
+}
+
+// Remembers the last packet it was sent and replays it to machine 0 whenever the
+// network goes idle, stopping once it sends the same y value twice in a row
+#[derive(Debug, Default)]
+pub struct Nat {
+    first: Option<Packet>,
+    last: Option<Packet>,
+    last_sent_y: Option<i64>,
+    repeated_y: Option<i64>,
+}
+
+impl Nat {
+    pub fn new() -> Nat {
+        Nat::default()
+    }
+
+    pub fn first(&self) -> Option<Packet> {
+        self.first
+    }
+
+    pub fn repeated_y(&self) -> Option<i64> {
+        self.repeated_y
+    }
+}
+
+impl Monitor for Nat {
+    fn receive(&mut self, packet: Packet) -> Action {
+        if self.first.is_none() {
+            self.first = Some(packet);
+        }
+
+        self.last = Some(Packet {dest: 0, ..packet});
+
+        Action::Continue
+    }
+
+    fn idle(&mut self) -> Action {
+        let packet = match self.last {
+            Some(packet) => packet,
+            None => return Action::Continue,
+        };
+
+        if self.last_sent_y == Some(packet.y) {
+            self.repeated_y = Some(packet.y);
+            return Action::Stop;
+        }
+
+        self.last_sent_y = Some(packet.y);
+
+        Action::Send(packet)
+    }
+}
+
+// Pass a packet on to the machine it's addressed to
+fn send<F: FnMut(usize, Packet)>(packet: Packet, size: usize, deliver: &mut F) -> Result<(), NetworkError> {
+    if packet.dest < 0 || packet.dest as usize >= size {
+        return Err(NetworkError::BadAddress(packet));
+    }
+
+    deliver(packet.dest as usize, packet);
+
+    Ok(())
+}
+
+// Carry out a monitor action, returning whether it asked to stop
+fn apply<F: FnMut(usize, Packet)>(action: Action, size: usize, deliver: &mut F) -> Result<bool, NetworkError> {
+    match action {
+        Action::Continue => Ok(false),
+        Action::Stop => Ok(true),
+        Action::Send(packet) => send(packet, size, deliver).map(|_| false),
+    }
+}
+
+// Hand a packet to the monitor or the machine it's for, returning whether to stop
+fn route<M: Monitor, F: FnMut(usize, Packet)>(
+    packet: Packet,
+    size: usize,
+    monitor_address: i64,
+    monitor: &mut M,
+    deliver: &mut F,
+) -> Result<bool, NetworkError> {
+    if packet.dest == monitor_address {
+        apply(monitor.receive(packet), size, deliver)
+    } else {
+        send(packet, size, deliver).map(|_| false)
+    }
+}
+
+#[derive(Debug)]
+pub struct Network {
+    machines: Vec<Machine<VecDeque<i64>, ()>>,
+    monitor_address: i64,
+}
+
+impl Network {
+    // Boot one copy of the program per address, each fed its address first
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let machines = (0..size).map(|address| {
+            let mut machine = Machine::with_io(program.to_vec(), VecDeque::new(), ());
+            machine.feed(address as i64);
+            machine
+        }).collect();
+
+        Network {machines, monitor_address: MONITOR_ADDRESS}
+    }
+
+    pub fn set_monitor_address(&mut self, address: i64) {
+        self.monitor_address = address;
+    }
+
+    // Give each machine a turn in order until the monitor stops the network or every
+    // machine has halted
+    pub fn run<M: Monitor>(&mut self, monitor: &mut M) -> Result<(), NetworkError> {
+        let size = self.machines.len();
+        let mut partial: Vec<Vec<i64>> = vec![vec![]; size];
+
+        loop {
+            let mut idle = true;
+            let mut packets = vec![];
+
+            for (idx, machine) in self.machines.iter_mut().enumerate() {
+                // A halted machine never reads what it's sent, so it can't hold up idling
+                if machine.status() == Status::Halted {
+                    continue;
+                }
+
+                if machine.input().is_empty() {
+                    machine.feed(NO_PACKET);
+                } else {
+                    idle = false;
+                }
+
+                loop {
+                    match machine.run() {
+                        Ok(Status::Output(value)) => {
+                            idle = false;
+                            partial[idx].push(value);
+
+                            if partial[idx].len() == 3 {
+                                packets.push(Packet {dest: partial[idx][0], x: partial[idx][1], y: partial[idx][2]});
+                                partial[idx].clear();
+                            }
+                        },
+                        Ok(_) => break,
+                        Err(error) => return Err(NetworkError::Failed(idx, error)),
+                    }
+                }
+            }
+
+            let machines = &mut self.machines;
+            let mut deliver = |idx: usize, packet: Packet| {
+                machines[idx].feed(packet.x);
+                machines[idx].feed(packet.y);
+            };
+
+            for packet in packets {
+                if route(packet, size, self.monitor_address, monitor, &mut deliver)? {
+                    return Ok(());
+                }
+            }
+
+            if idle && apply(monitor.idle(), size, &mut deliver)? {
+                return Ok(());
+            }
+
+            if self.machines.iter().all(|machine| machine.status() == Status::Halted) {
+                return Ok(());
+            }
+        }
+    }
+
+    // As run(), but with each machine on its own thread and packets passed over channels
+    pub fn run_threaded<M: Monitor>(self, monitor: &mut M) -> Result<(), NetworkError> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (event_tx, event_rx) = channel();
+        let mut nodes = vec![];
+        let mut handles = vec![];
+
+        for (idx, machine) in self.machines.into_iter().enumerate() {
+            let (tx, rx) = channel();
+            let state = Arc::new(NodeState::default());
+
+            let node_state = Arc::clone(&state);
+            let node_shutdown = Arc::clone(&shutdown);
+            let node_events = event_tx.clone();
+
+            handles.push(thread::spawn(move || {
+                run_node(idx, machine, rx, node_state, node_shutdown, node_events);
+            }));
+
+            nodes.push((tx, state));
+        }
+
+        drop(event_tx);
+
+        let result = route_events(&event_rx, &nodes, self.monitor_address, monitor);
+
+        shutdown.store(true, Ordering::SeqCst);
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        result
+    }
+}
+
+#[derive(Debug, Default)]
+struct NodeState {
+    // Set when the machine asked for input again straight after being told there was
+    // none, with no I/O in between
+    idle: AtomicBool,
+    // Bumped on every real input or output, so the router can spot activity
+    activity: AtomicUsize,
+    // Values sent to the machine that it hasn't read yet
+    pending: AtomicUsize,
+}
+
+enum Event {
+    Packet(Packet),
+    Halted,
+    Failed(usize, IntcodeError),
+}
+
+// How many steps a threaded machine takes between checks for shutdown
+const BATCH: usize = 10_000;
+
+// As Machine::run(), but giving up with Status::Running after BATCH steps
+fn run_batch(machine: &mut Machine<VecDeque<i64>, ()>) -> Result<Status, IntcodeError> {
+    for _ in 0..BATCH {
+        match machine.step()? {
+            Status::Running => continue,
+            status => return Ok(status),
+        }
+    }
+
+    Ok(Status::Running)
+}
+
+fn run_node(
+    idx: usize,
+    mut machine: Machine<VecDeque<i64>, ()>,
+    rx: Receiver<i64>,
+    state: Arc<NodeState>,
+    shutdown: Arc<AtomicBool>,
+    events: Sender<Event>,
+) {
+    let mut partial = vec![];
+    // Whether the last value read was NO_PACKET, as a machine that's just been told
+    // there's nothing may still have work to do before it asks again
+    let mut starved = false;
+
+    loop {
+        match run_batch(&mut machine) {
+            // Still computing, so see whether the network has stopped
+            Ok(Status::Running) => {
+                state.idle.store(false, Ordering::SeqCst);
+
+                if shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+            },
+            Ok(Status::Output(value)) => {
+                if shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                starved = false;
+                state.idle.store(false, Ordering::SeqCst);
+                state.activity.fetch_add(1, Ordering::SeqCst);
+                partial.push(value);
+
+                if partial.len() == 3 {
+                    let _ = events.send(Event::Packet(Packet {dest: partial[0], x: partial[1], y: partial[2]}));
+                    partial.clear();
+                }
+            },
+            Ok(Status::NeedsInput) => {
+                if shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                match rx.try_recv() {
+                    Ok(value) => {
+                        starved = false;
+                        state.idle.store(false, Ordering::SeqCst);
+                        state.activity.fetch_add(1, Ordering::SeqCst);
+                        state.pending.fetch_sub(1, Ordering::SeqCst);
+                        machine.feed(value);
+                    },
+                    Err(TryRecvError::Empty) => {
+                        state.idle.store(starved, Ordering::SeqCst);
+                        starved = true;
+                        machine.feed(NO_PACKET);
+                        thread::yield_now();
+                    },
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            },
+            Ok(_) => {
+                state.idle.store(true, Ordering::SeqCst);
+                let _ = events.send(Event::Halted);
+                return;
+            },
+            Err(error) => {
+                let _ = events.send(Event::Failed(idx, error));
+                return;
+            },
+        }
+    }
+}
+
+fn route_events<M: Monitor>(
+    events: &Receiver<Event>,
+    nodes: &[(Sender<i64>, Arc<NodeState>)],
+    monitor_address: i64,
+    monitor: &mut M,
+) -> Result<(), NetworkError> {
+    let size = nodes.len();
+    let mut halted = 0;
+
+    let mut deliver = |idx: usize, packet: Packet| {
+        let (tx, state) = &nodes[idx];
+
+        // Count the values as pending before sending so the machine never looks idle
+        // while they're in flight
+        state.pending.fetch_add(2, Ordering::SeqCst);
+        let _ = tx.send(packet.x);
+        let _ = tx.send(packet.y);
+    };
+
+    let activity = || -> Option<Vec<usize>> {
+        let quiet = nodes.iter().all(|(_, state)| {
+            state.idle.load(Ordering::SeqCst) && state.pending.load(Ordering::SeqCst) == 0
+        });
+
+        if quiet {
+            Some(nodes.iter().map(|(_, state)| state.activity.load(Ordering::SeqCst)).collect())
+        } else {
+            None
+        }
+    };
+
+    loop {
+        let event = match events.recv_timeout(Duration::from_millis(1)) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {
+                // Only call the network idle if nothing happened while we were looking
+                let before = activity();
+                thread::sleep(Duration::from_millis(1));
+
+                match events.try_recv() {
+                    Ok(event) => event,
+                    Err(_) => {
+                        let quiet = before.is_some() && before == activity();
+
+                        if quiet && apply(monitor.idle(), size, &mut deliver)? {
+                            return Ok(());
+                        }
+
+                        continue;
+                    },
+                }
+            },
+        };
+
+        match event {
+            Event::Packet(packet) => {
+                if route(packet, size, monitor_address, monitor, &mut deliver)? {
+                    return Ok(());
+                }
+            },
+            Event::Halted => {
+                halted += 1;
+
+                if halted == size {
+                    return Ok(());
+                }
+            },
+            Event::Failed(idx, error) => return Err(NetworkError::Failed(idx, error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    // Machine 0 sends (255, 0, 5) on boot. Every machine then echoes anything it
+    // receives on to 255
+    const ECHO: &str = "3,50,1005,50,11,104,255,104,0,104,5,3,51,1008,51,-1,52,1005,52,11,3,53,104,255,4,51,4,53,1105,1,11,99";
+
+    #[test]
+    fn test_round_robin() {
+        let mut nat = Nat::new();
+        let mut network = Network::new(&parse_program(ECHO).unwrap(), 3);
+
+        assert_eq!(network.run(&mut nat), Ok(()));
+        assert_eq!(nat.first(), Some(Packet {dest: 255, x: 0, y: 5}));
+        assert_eq!(nat.repeated_y(), Some(5));
+    }
+
+    #[test]
+    fn test_threaded() {
+        let mut nat = Nat::new();
+        let network = Network::new(&parse_program(ECHO).unwrap(), 3);
+
+        assert_eq!(network.run_threaded(&mut nat), Ok(()));
+        assert_eq!(nat.first(), Some(Packet {dest: 255, x: 0, y: 5}));
+        assert_eq!(nat.repeated_y(), Some(5));
+    }
+
+    #[test]
+    fn test_halted_machine() {
+        // Machine 0 sends (255, 0, 5) and then reads forever, while the others halt
+        let program = parse_program("3,100,1005,100,16,104,255,104,0,104,5,3,101,1105,1,11,99").unwrap();
+        let mut nat = Nat::new();
+        let mut network = Network::new(&program, 2);
+
+        assert_eq!(network.run(&mut nat), Ok(()));
+        assert_eq!(nat.repeated_y(), Some(5));
+
+        let mut nat = Nat::new();
+
+        assert_eq!(Network::new(&program, 2).run_threaded(&mut nat), Ok(()));
+        assert_eq!(nat.repeated_y(), Some(5));
+    }
+
+    // Stops the network as soon as anything is sent to it, counting the idle calls before
+    #[derive(Default)]
+    struct StopOnReceive {
+        idles: usize,
+    }
+
+    impl Monitor for StopOnReceive {
+        fn receive(&mut self, _packet: Packet) -> Action {
+            Action::Stop
+        }
+
+        fn idle(&mut self) -> Action {
+            self.idles += 1;
+            Action::Continue
+        }
+    }
+
+    #[test]
+    fn test_stop_while_busy() {
+        // Send (255, 0, 5) and then spin forever without any I/O
+        let program = parse_program("104,255,104,0,104,5,1105,1,6").unwrap();
+
+        assert_eq!(Network::new(&program, 2).run_threaded(&mut StopOnReceive::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_busy_after_no_packet() {
+        // Read the address and a NO_PACKET, count to 100000 and then send (255, 0, 7)
+        let program = parse_program("3,100,3,100,1001,101,1,101,1007,101,100000,102,1005,102,4,104,255,104,0,104,7,99").unwrap();
+        let mut monitor = StopOnReceive::default();
+
+        assert_eq!(Network::new(&program, 1).run(&mut monitor), Ok(()));
+        assert_eq!(monitor.idles, 0);
+
+        let mut monitor = StopOnReceive::default();
+
+        assert_eq!(Network::new(&program, 1).run_threaded(&mut monitor), Ok(()));
+        assert_eq!(monitor.idles, 0);
+    }
+
+    #[test]
+    fn test_bad_address() {
+        let mut nat = Nat::new();
+        let mut network = Network::new(&parse_program("104,7,104,1,104,2,99").unwrap(), 2);
+
+        assert_eq!(network.run(&mut nat), Err(NetworkError::BadAddress(Packet {dest: 7, x: 1, y: 2})));
+    }
+}
@@ -0,0 +1,146 @@
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OpcodeMode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OpcodeError {
+    UnknownOpcode,
+    InvalidMode(i64),
+}
+
+pub fn parse_program(input: &str) -> Result<Vec<i64>, ParseIntError> {
+    input.trim().split(',').map(|x| i64::from_str(x.trim())).collect()
+}
+
+pub fn format_program(program: &[i64]) -> String {
+    program.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(",")
+}
+
+// The number of parameters each opcode takes
+pub fn param_count(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 => Some(3),
+        2 => Some(3),
+        3 => Some(1),
+        4 => Some(1),
+        5 => Some(2),
+        6 => Some(2),
+        7 => Some(3),
+        8 => Some(3),
+        9 => Some(1),
+        99 => Some(0),
+        _ => None,
+    }
+}
+
+// The mnemonics used by traces, listings and the assembler
+pub fn mnemonic(opcode: i64) -> Option<&'static str> {
+    match opcode {
+        1 => Some("ADD"),
+        2 => Some("MUL"),
+        3 => Some("IN"),
+        4 => Some("OUT"),
+        5 => Some("JMPT"),
+        6 => Some("JMPF"),
+        7 => Some("LT"),
+        8 => Some("EQ"),
+        9 => Some("ARB"),
+        99 => Some("HALT"),
+        _ => None,
+    }
+}
+
+pub fn from_mnemonic(mnemonic: &str) -> Option<i64> {
+    match mnemonic.to_uppercase().as_str() {
+        "ADD" => Some(1),
+        "MUL" => Some(2),
+        "IN" => Some(3),
+        "OUT" => Some(4),
+        "JMPT" => Some(5),
+        "JMPF" => Some(6),
+        "LT" => Some(7),
+        "EQ" => Some(8),
+        "ARB" => Some(9),
+        "HALT" => Some(99),
+        _ => None,
+    }
+}
+
+// The index of the parameter an opcode writes to, if it writes at all
+pub fn write_param(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(2),
+        3 => Some(0),
+        _ => None,
+    }
+}
+
+pub fn parse_opcode(instruction: i64) -> Result<(i64, Vec<OpcodeMode>), OpcodeError> {
+    if instruction < 0 {
+        return Err(OpcodeError::UnknownOpcode);
+    }
+
+    // The opcode lives in the last two decimal digits, the modes in the rest
+    let opcode = instruction % 100;
+    let mut mode_digits = instruction / 100;
+
+    // Extract the number of parameters for an opcode and use that to determine whether
+    // each param should be in immediate or position mode
+    let num_params = match param_count(opcode) {
+        Some(count) => count,
+        None => return Err(OpcodeError::UnknownOpcode),
+    };
+
+    // Modes are read right-to-left, with missing digits defaulting to position mode
+    let mut modes = Vec::with_capacity(num_params);
+
+    for _ in 0..num_params {
+        modes.push(match mode_digits % 10 {
+            0 => OpcodeMode::Position,
+            1 => OpcodeMode::Immediate,
+            2 => OpcodeMode::Relative,
+            mode => return Err(OpcodeError::InvalidMode(mode)),
+        });
+
+        mode_digits /= 10;
+    }
+
+    // Return the opcode and modes
+    Ok((opcode, modes))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(parse_program("1002,4,3,4,33\n"), Ok(vec![1002, 4, 3, 4, 33]));
+        assert_eq!(parse_program("3,12,-1,0,1,9"), Ok(vec![3, 12, -1, 0, 1, 9]));
+        assert_eq!(parse_program("104,1125899906842624,99"), Ok(vec![104, 1125899906842624, 99]));
+        assert!(parse_program("1,x,3").is_err());
+        assert_eq!(format_program(&[1002, 4, -3]), "1002,4,-3");
+    }
+
+    #[test]
+    fn test_parse_opcode() {
+        assert_eq!(parse_opcode(1), Ok((1, vec![OpcodeMode::Position, OpcodeMode::Position, OpcodeMode::Position])));
+        assert_eq!(parse_opcode(2), Ok((2, vec![OpcodeMode::Position, OpcodeMode::Position, OpcodeMode::Position])));
+        assert_eq!(parse_opcode(3), Ok((3, vec![OpcodeMode::Position])));
+        assert_eq!(parse_opcode(4), Ok((4, vec![OpcodeMode::Position])));
+        assert_eq!(parse_opcode(1002), Ok((2, vec![OpcodeMode::Position, OpcodeMode::Immediate, OpcodeMode::Position])));
+        assert_eq!(parse_opcode(99), Ok((99, vec![])));
+        assert_eq!(parse_opcode(209), Ok((9, vec![OpcodeMode::Relative])));
+        assert_eq!(parse_opcode(21101), Ok((1, vec![OpcodeMode::Immediate, OpcodeMode::Immediate, OpcodeMode::Relative])));
+        assert_eq!(parse_opcode(42), Err(OpcodeError::UnknownOpcode));
+        assert_eq!(parse_opcode(-1), Err(OpcodeError::UnknownOpcode));
+        assert_eq!(parse_opcode(1301), Err(OpcodeError::InvalidMode(3)));
+    }
+}
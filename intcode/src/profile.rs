@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::disasm::{disassemble_from, Line};
+use super::opcode::mnemonic;
+use super::trace::{TraceEvent, Tracer};
+
+// Counts what a machine executes, fed by the tracer hook
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    cycles: u64,
+    addresses: HashMap<usize, u64>,
+    opcodes: BTreeMap<i64, u64>,
+    // Jumps taken to the same or an earlier address, keyed by (from, to)
+    back_edges: HashMap<(usize, usize), u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn count(&self, address: usize) -> u64 {
+        *self.addresses.get(&address).unwrap_or(&0)
+    }
+
+    pub fn opcodes(&self) -> &BTreeMap<i64, u64> {
+        &self.opcodes
+    }
+
+    // The n most executed addresses, busiest first
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut addresses: Vec<(usize, u64)> = self.addresses.iter().map(|(&address, &count)| (address, count)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(n);
+        addresses
+    }
+
+    // Every back-edge taken as (from, to, count), busiest first
+    pub fn loops(&self) -> Vec<(usize, usize, u64)> {
+        let mut edges: Vec<(usize, usize, u64)> = self.back_edges.iter().map(|(&(from, to), &count)| (from, to, count)).collect();
+        edges.sort_by(|a, b| b.2.cmp(&a.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+        edges
+    }
+
+    // A disassembly of the program with execution counts down the left. Everything that
+    // ran is used as a starting point, so code only reached by computed jumps is listed
+    pub fn annotate(&self, program: &[i64]) -> Vec<String> {
+        let mut roots: Vec<usize> = self.addresses.keys().cloned().collect();
+        roots.sort();
+        roots.insert(0, 0);
+
+        disassemble_from(program, &roots).iter().map(|line| {
+            let count = match line {
+                Line::Code(instruction) if self.count(instruction.address) > 0 => self.count(instruction.address).to_string(),
+                _ => String::new(),
+            };
+
+            format!("{:>10}  {}", count, line)
+        }).collect()
+    }
+
+    pub fn report(&self, hottest: usize) -> String {
+        let mut lines = vec![format!("cycles: {}", self.cycles), "opcodes:".to_string()];
+
+        for (&opcode, &count) in &self.opcodes {
+            let share = 100.0 * count as f64 / self.cycles as f64;
+            lines.push(format!("  {:<6}{:>10}  {:5.1}%", mnemonic(opcode).unwrap_or("???"), count, share));
+        }
+
+        lines.push("hottest addresses:".to_string());
+
+        for (address, count) in self.hottest(hottest) {
+            lines.push(format!("  {:<6}{:>10}", address, count));
+        }
+
+        lines.push("loops:".to_string());
+
+        for (from, to, count) in self.loops() {
+            lines.push(format!("  {} -> {} taken {} times", from, to, count));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.cycles += 1;
+        *self.addresses.entry(event.ip).or_insert(0) += 1;
+        *self.opcodes.entry(event.opcode).or_insert(0) += 1;
+
+        let taken = match event.opcode {
+            5 => event.operands[0] != 0,
+            6 => event.operands[0] == 0,
+            _ => false,
+        };
+
+        // A jump to a negative address fails before it's traced, so the target is valid
+        if taken && event.operands[1] as usize <= event.ip {
+            *self.back_edges.entry((event.ip, event.operands[1] as usize)).or_insert(0) += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Machine, Status};
+    use crate::opcode::parse_program;
+
+    // Count down from 3, outputting each value
+    const COUNTDOWN: &str = "4,11,1001,11,-1,11,1005,11,0,99,0,3";
+
+    #[test]
+    fn test_profile() {
+        let program = parse_program(COUNTDOWN).unwrap();
+        let mut machine = Machine::new(program.clone()).with_tracer(Profiler::new());
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+
+        let profiler = machine.tracer();
+
+        assert_eq!(profiler.cycles(), 10);
+        assert_eq!(profiler.count(0), 3);
+        assert_eq!(profiler.count(9), 1);
+        assert_eq!(profiler.opcodes().get(&5), Some(&3));
+        assert_eq!(profiler.hottest(2), vec![(0, 3), (2, 3)]);
+        assert_eq!(profiler.loops(), vec![(6, 0, 2)]);
+        assert_eq!(profiler.annotate(&program)[..2], ["         3  0:\tOUT   [11]", "         3  2:\tADD   [11], #-1\t-> [11]"]);
+        assert!(profiler.report(1).ends_with("hottest addresses:\n  0              3\nloops:\n  6 -> 0 taken 2 times"));
+    }
+}
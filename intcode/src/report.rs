@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use super::io::{Input, Output};
+use super::machine::Machine;
+use super::trace::Tracer;
+
+// How a binary prints the results of a run
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    // One output per line
+    Plain,
+    // The outputs on one comma-separated line, the same as a program or input list
+    Csv,
+    // The outputs along with the final memory, including any far off sparse cells, and
+    // cycle count
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<OutputFormat, String> {
+        match text {
+            "plain" => Ok(OutputFormat::Plain),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("'{}' should be plain, csv or json", text)),
+        }
+    }
+}
+
+// What a run produced, for printing in an OutputFormat
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub outputs: Vec<i64>,
+    pub memory: Vec<i64>,
+    // Cells written too far past the end of memory to keep densely, by address
+    pub sparse: Vec<(usize, i64)>,
+    pub cycles: u64,
+}
+
+impl Report {
+    // The outputs are whatever the machine's output device kept
+    pub fn new<I: Input, O: Output, T: Tracer>(machine: &Machine<I, O, T>) -> Report {
+        Report {
+            outputs: machine.output().written(),
+            memory: machine.memory().cells().to_vec(),
+            sparse: machine.memory().sparse(),
+            cycles: machine.cycles(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> String {
+        let join = |values: &[i64], separator| values.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(separator);
+        // JSON keys have to be strings, so the addresses are quoted
+        let sparse = self.sparse.iter().map(|(address, value)| format!("\"{}\":{}", address, value)).collect::<Vec<String>>().join(",");
+
+        match format {
+            OutputFormat::Plain => join(&self.outputs, "\n"),
+            OutputFormat::Csv => join(&self.outputs, ","),
+            OutputFormat::Json => format!(
+                "{{\"outputs\":[{}],\"memory\":[{}],\"sparse\":{{{}}},\"cycles\":{}}}",
+                join(&self.outputs, ","),
+                join(&self.memory, ","),
+                sparse,
+                self.cycles,
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    #[test]
+    fn test_formats() {
+        let mut machine = Machine::new(parse_program("104,7,4,0,99").unwrap());
+        machine.run_until_blocked().unwrap();
+
+        let report = Report::new(&machine);
+
+        assert_eq!(report.format(OutputFormat::Plain), "7\n104");
+        assert_eq!(report.format(OutputFormat::Csv), "7,104");
+        assert_eq!(report.format(OutputFormat::Json), "{\"outputs\":[7,104],\"memory\":[104,7,4,0,99],\"sparse\":{},\"cycles\":3}");
+        assert!(OutputFormat::from_str("xml").is_err());
+
+        // Write 5 far past the end of memory
+        let far = crate::memory::DENSE_LIMIT * 4;
+        let mut machine = Machine::new(parse_program(&format!("1101,2,3,{},99", far)).unwrap());
+        machine.run_until_blocked().unwrap();
+
+        let report = Report::new(&machine);
+
+        assert_eq!(report.sparse, vec![(far, 5)]);
+        assert_eq!(
+            report.format(OutputFormat::Json),
+            format!("{{\"outputs\":[],\"memory\":[1101,2,3,{},99],\"sparse\":{{\"{}\":5}},\"cycles\":2}}", far, far),
+        );
+    }
+}
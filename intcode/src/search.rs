@@ -0,0 +1,351 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use super::machine::Machine;
+use super::memory::{Memory, MemoryError};
+use super::report::Report;
+use super::snapshot::Snapshot;
+use super::trace::Tracer;
+use super::watchdog::Limits;
+
+// A value to put in a memory cell before a run, written ADDR=VALUE
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Patch {
+    pub address: usize,
+    pub value: i64,
+}
+
+fn split_assignment(text: &str) -> Result<(usize, &str), String> {
+    match text.find('=') {
+        Some(idx) => {
+            let address = &text[..idx];
+            let address = usize::from_str(address.trim()).map_err(|_| format!("'{}' is not a valid address", address))?;
+            Ok((address, text[idx + 1..].trim()))
+        },
+        None => Err(format!("'{}' should start with ADDR=", text)),
+    }
+}
+
+fn parse_value(text: &str) -> Result<i64, String> {
+    i64::from_str(text).map_err(|_| format!("'{}' is not a valid value", text))
+}
+
+impl FromStr for Patch {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Patch, String> {
+        let (address, value) = split_assignment(text)?;
+
+        Ok(Patch {address, value: parse_value(value)?})
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] = {}", self.address, self.value)
+    }
+}
+
+// A memory cell to try every value of START..END in, written ADDR=START..END
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vary {
+    pub address: usize,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Vary {
+    // Worked out in i128 as a range can be wider than i64 even once it's parsed
+    pub fn len(&self) -> u64 {
+        (self.end as i128 - self.start as i128).max(0) as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FromStr for Vary {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Vary, String> {
+        let (address, range) = split_assignment(text)?;
+
+        let (start, end) = match range.find("..") {
+            Some(idx) => (parse_value(range[..idx].trim())?, parse_value(range[idx + 2..].trim())?),
+            None => return Err(format!("'{}' should be START..END", range)),
+        };
+
+        match end.checked_sub(start) {
+            Some(width) if width > 0 => Ok(Vary {address, start, end}),
+            Some(_) => Err(format!("'{}' is an empty range", range)),
+            None => Err(format!("'{}' is too wide a range", range)),
+        }
+    }
+}
+
+// The memory cell that should end up holding a value
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Goal {
+    pub address: usize,
+    pub value: i64,
+}
+
+// Put patches into memory, which keeps far off addresses sparse and refuses any past the
+// memory limit
+pub fn patch(memory: &mut Memory, patches: &[Patch]) -> Result<(), MemoryError> {
+    for patch in patches {
+        memory.set(patch.address, patch.value)?;
+    }
+
+    Ok(())
+}
+
+// How many combinations of values there are to try, or None if there are too many to
+// count, which is far too many to search
+pub fn combinations(vary: &[Vary]) -> Option<u64> {
+    vary.iter().try_fold(1u64, |total, vary| total.checked_mul(vary.len()))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SearchError {
+    // A combination can't be put into memory, which means the search itself is wrong
+    Memory(MemoryError),
+    // There are too many combinations to count, never mind try
+    TooManyCombinations,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchError::Memory(error) => write!(f, "{}", error),
+            SearchError::TooManyCombinations => write!(f, "too many combinations to search"),
+        }
+    }
+}
+
+fn total(vary: &[Vary]) -> Result<u64, SearchError> {
+    combinations(vary).ok_or(SearchError::TooManyCombinations)
+}
+
+// The index'th combination, counting like nested loops with the last cell changing
+// fastest, so any slice of 0..combinations can be searched on its own
+pub fn combination(vary: &[Vary], mut index: u64) -> Vec<Patch> {
+    let mut patches = vec![];
+
+    for vary in vary.iter().rev() {
+        patches.push(Patch {address: vary.address, value: vary.start.wrapping_add((index % vary.len()) as i64)});
+        index /= vary.len();
+    }
+
+    patches.reverse();
+    patches
+}
+
+// A combination that reached the goal, and the run that did it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub patches: Vec<Patch>,
+    pub report: Report,
+}
+
+// Run the machine saved in start with one combination, returning the run if it reached
+// the goal. A combination that makes the program crash or hang just isn't the answer,
+// but one that can't be put into memory means the search itself is wrong
+pub fn attempt<T: Tracer>(start: &Snapshot, patches: Vec<Patch>, goal: Goal, limits: &Limits, tracer: T) -> Result<Option<Match>, MemoryError> {
+    let mut machine = Machine::from_snapshot(start).with_tracer(tracer);
+
+    patch(machine.memory_mut(), &patches)?;
+    limits.apply(&mut machine);
+
+    match machine.run_until_blocked() {
+        Ok(_) if machine.memory().get(goal.address) == Ok(goal.value) => Ok(Some(Match {patches, report: Report::new(&machine)})),
+        _ => Ok(None),
+    }
+}
+
+// Try every combination in order, stopping at the first match unless all are wanted
+pub fn seek<T: Tracer>(start: &Snapshot, vary: &[Vary], goal: Goal, limits: &Limits, tracer: &mut T, all: bool) -> Result<Vec<Match>, SearchError> {
+    let mut matches = vec![];
+
+    for index in 0..total(vary)? {
+        if let Some(found) = attempt(start, combination(vary, index), goal, limits, &mut *tracer).map_err(SearchError::Memory)? {
+            matches.push(found);
+
+            if !all {
+                break;
+            }
+        }
+    }
+
+    Ok(matches)
+}
+
+// How many indices a thread claims at a time in a parallel sweep
+const CHUNK: u64 = 64;
+
+// Call f with every index in 0..total across a number of threads, returning the results
+// it gives in index order. Threads claim chunks of indices in order, and if only the first
+// result is wanted nothing after the earliest one so far is started, so the outcome is
+// the same as a loop over the indices. Each index tried bumps the tried counter, which
+// can be read from another thread to follow progress
+pub fn sweep<R: Send, F: Fn(u64) -> Option<R> + Sync>(total: u64, threads: usize, first_only: bool, tried: &AtomicU64, f: F) -> Vec<R> {
+    let next = AtomicU64::new(0);
+    // The earliest result so far
+    let first = AtomicU64::new(u64::MAX);
+    let found = Mutex::new(vec![]);
+
+    let wanted = |index| !first_only || index < first.load(Ordering::Relaxed);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let chunk = next.fetch_add(CHUNK, Ordering::Relaxed);
+
+                if chunk >= total || !wanted(chunk) {
+                    break;
+                }
+
+                for index in (chunk..total.min(chunk.saturating_add(CHUNK))).take_while(|&index| wanted(index)) {
+                    let result = f(index);
+                    tried.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(result) = result {
+                        first.fetch_min(index, Ordering::Relaxed);
+                        found.lock().unwrap().push((index, result));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut found = found.into_inner().unwrap();
+    found.sort_by_key(|(index, _)| *index);
+
+    if first_only {
+        found.truncate(1);
+    }
+
+    found.into_iter().map(|(_, result)| result).collect()
+}
+
+// As seek, but sweeping the combinations across a number of threads, without tracing
+// since the traces would be interleaved
+pub fn seek_parallel(start: &Snapshot, vary: &[Vary], goal: Goal, limits: &Limits, threads: usize, all: bool, tried: &AtomicU64) -> Result<Vec<Match>, SearchError> {
+    let total = total(vary)?;
+
+    // Every combination patches the same addresses, so if one fits in memory they all do
+    if total > 0 {
+        let mut memory = Memory::new(vec![]);
+        memory.set_limit(start.memory_limit);
+        patch(&mut memory, &combination(vary, 0)).map_err(SearchError::Memory)?;
+    }
+
+    // The addresses were checked above
+    Ok(sweep(total, threads, !all, tried, |index| attempt(start, combination(vary, index), goal, limits, ()).unwrap_or(None)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Patch::from_str("1=-12"), Ok(Patch {address: 1, value: -12}));
+        assert_eq!(Vary::from_str("2=0..100"), Ok(Vary {address: 2, start: 0, end: 100}));
+        assert!(Patch::from_str("1").is_err());
+        assert!(Vary::from_str("x=0..1").is_err());
+        assert!(Vary::from_str("1=5..5").is_err());
+        assert!(Vary::from_str("1=-9223372036854775808..9223372036854775807").is_err());
+    }
+
+    #[test]
+    fn test_combinations() {
+        let vary = [Vary {address: 1, start: 0, end: 2}, Vary {address: 2, start: 5, end: 8}];
+
+        assert_eq!(combinations(&vary), Some(6));
+        assert_eq!(combination(&vary, 0), vec![Patch {address: 1, value: 0}, Patch {address: 2, value: 5}]);
+        assert_eq!(combination(&vary, 4), vec![Patch {address: 1, value: 1}, Patch {address: 2, value: 6}]);
+        assert_eq!(combination(&[], 0), vec![]);
+        assert_eq!(combinations(&[Vary {address: 1, start: 0, end: 100000}; 4]), None);
+
+        let start = Snapshot::new(vec![99]);
+        let huge = [Vary {address: 1, start: 0, end: 100000}; 4];
+        let goal = Goal {address: 0, value: 99};
+
+        assert_eq!(seek(&start, &huge, goal, &Limits::default(), &mut (), true), Err(SearchError::TooManyCombinations));
+        assert_eq!(seek_parallel(&start, &huge, goal, &Limits::default(), 2, true, &AtomicU64::new(0)), Err(SearchError::TooManyCombinations));
+
+        let wide = [Vary {address: 1, start: i64::MIN, end: i64::MAX}];
+
+        assert_eq!(combinations(&wide), Some(u64::MAX));
+        assert_eq!(combination(&wide, u64::MAX - 1), vec![Patch {address: 1, value: i64::MAX - 1}]);
+    }
+
+    #[test]
+    fn test_seek() {
+        // Add the values at 1 and 2 into 0
+        let start = Snapshot::new(parse_program("1101,0,0,0,99").unwrap());
+        let vary = [Vary {address: 1, start: 0, end: 5}, Vary {address: 2, start: 0, end: 5}];
+        let goal = Goal {address: 0, value: 3};
+
+        let found: Vec<Vec<i64>> = seek(&start, &vary, goal, &Limits::default(), &mut (), true).unwrap().iter()
+            .map(|found| found.patches.iter().map(|patch| patch.value).collect())
+            .collect();
+
+        assert_eq!(found, vec![vec![0, 3], vec![1, 2], vec![2, 1], vec![3, 0]]);
+        assert_eq!(seek(&start, &vary, goal, &Limits::default(), &mut (), false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_seek_parallel() {
+        let start = Snapshot::new(parse_program("1101,0,0,0,99").unwrap());
+        let vary = [Vary {address: 1, start: 0, end: 200}, Vary {address: 2, start: 0, end: 200}];
+        let goal = Goal {address: 0, value: 300};
+        let limits = Limits::default();
+        let tried = AtomicU64::new(0);
+
+        let all = seek_parallel(&start, &vary, goal, &limits, 4, true, &tried).unwrap();
+
+        assert_eq!(all, seek(&start, &vary, goal, &limits, &mut (), true).unwrap());
+        assert_eq!(all.len(), 99);
+        assert_eq!(tried.load(Ordering::Relaxed), 40000);
+
+        // The first match is at 101 + 199, so the search stops well short of the end
+        let tried = AtomicU64::new(0);
+        let first = seek_parallel(&start, &vary, goal, &limits, 4, false, &tried).unwrap();
+
+        assert_eq!(first, all[..1]);
+        assert!(tried.load(Ordering::Relaxed) < 40000);
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut memory = Memory::new(vec![1, 2]);
+        let far = crate::memory::DENSE_LIMIT * 4;
+
+        assert_eq!(patch(&mut memory, &[Patch {address: 1, value: 5}, Patch {address: far, value: 7}]), Ok(()));
+        assert_eq!(memory.cells(), &[1, 5]);
+        assert_eq!(memory.sparse(), vec![(far, 7)]);
+
+        memory.set_limit(100);
+
+        assert!(patch(&mut memory, &[Patch {address: 100, value: 1}]).is_err());
+
+        let mut start = Snapshot::new(vec![99]);
+        start.memory_limit = 100;
+        let vary = [Vary {address: 100, start: 0, end: 2}];
+        let goal = Goal {address: 0, value: 99};
+
+        assert!(seek(&start, &vary, goal, &Limits::default(), &mut (), true).is_err());
+        assert!(seek_parallel(&start, &vary, goal, &Limits::default(), 2, true, &AtomicU64::new(0)).is_err());
+
+        let error = SearchError::Memory(MemoryError::OutOfRange {address: 100, limit: 100});
+
+        assert_eq!(seek(&start, &vary, goal, &Limits::default(), &mut (), true), Err(error));
+    }
+}
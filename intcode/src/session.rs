@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::IntcodeError;
+use super::io::Output;
+use super::machine::{Machine, Status};
+use super::trace::{TraceEvent, Tracer};
+
+pub const SESSION_VERSION: u32 = 1;
+
+const MAGIC: &str = "intcode-session";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Input(i64),
+    Output(i64),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input(value) => write!(f, "in {}", value),
+            Event::Output(value) => write!(f, "out {}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Every value a program read and wrote, in the order it happened
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Session, SessionError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.trim()));
+        let error = |line, message| Err(SessionError {line, message});
+
+        match lines.next() {
+            Some((_, header)) if header.starts_with(MAGIC) => {
+                let version = header[MAGIC.len()..].trim();
+
+                if u32::from_str(version) != Ok(SESSION_VERSION) {
+                    return error(1, format!("unsupported session version '{}'", version));
+                }
+            },
+            _ => return error(1, "not an Intcode session".to_string()),
+        }
+
+        let mut session = Session::new();
+
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let mut words = text.split_whitespace();
+            let kind = words.next().unwrap_or("");
+
+            let value = match words.next().map(i64::from_str) {
+                Some(Ok(value)) => value,
+                _ => return error(line, format!("'{}' should be 'in VALUE' or 'out VALUE'", text)),
+            };
+
+            match kind {
+                "in" => session.events.push(Event::Input(value)),
+                "out" => session.events.push(Event::Output(value)),
+                _ => return error(line, format!("unknown event '{}'", kind)),
+            }
+        }
+
+        Ok(session)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Session> {
+        let text = fs::read_to_string(path)?;
+
+        Session::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", MAGIC, SESSION_VERSION)?;
+
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Builds up a session from the tracer hook, which sees inputs and outputs in order
+// whatever devices the machine has
+#[derive(Clone, Debug, Default)]
+pub struct SessionRecorder {
+    session: Session,
+}
+
+impl SessionRecorder {
+    pub fn new() -> SessionRecorder {
+        SessionRecorder::default()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl Tracer for SessionRecorder {
+    fn trace(&mut self, event: &TraceEvent) {
+        match (event.opcode, event.write) {
+            (3, Some((_, value))) => self.session.events.push(Event::Input(value)),
+            (4, _) => self.session.events.push(Event::Output(event.operands[0])),
+            _ => (),
+        }
+    }
+}
+
+// What the program did instead of what the session expected
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Actual {
+    Output(i64),
+    NeedsInput,
+    Halted,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    // None for expected means the session had already ended
+    Diverged { index: usize, expected: Option<Event>, actual: Actual },
+    Failed(IntcodeError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Diverged {index, expected, actual} => {
+                let expected = match expected {
+                    Some(Event::Input(value)) => format!("to read {}", value),
+                    Some(Event::Output(value)) => format!("output {}", value),
+                    None => "the session to end".to_string(),
+                };
+
+                let actual = match actual {
+                    Actual::Output(value) => format!("output {}", value),
+                    Actual::NeedsInput => "asked for input".to_string(),
+                    Actual::Halted => "halted".to_string(),
+                };
+
+                write!(f, "diverged at event {}: expected {}, but the program {}", index + 1, expected, actual)
+            },
+            ReplayError::Failed(error) => write!(f, "program failed: {}", error),
+        }
+    }
+}
+
+// Run a machine against a recorded session, feeding it the recorded inputs and checking
+// each output. The replay succeeds if the program halts, or waits for input, exactly
+// where the session ends
+pub fn replay<O: Output, T: Tracer>(machine: &mut Machine<VecDeque<i64>, O, T>, session: &Session) -> Result<(), ReplayError> {
+    let mut index = 0;
+    let diverged = |index: usize, actual| Err(ReplayError::Diverged {index, expected: session.events.get(index).cloned(), actual});
+
+    loop {
+        match machine.run().map_err(ReplayError::Failed)? {
+            Status::Output(value) => {
+                if session.events.get(index) != Some(&Event::Output(value)) {
+                    return diverged(index, Actual::Output(value));
+                }
+
+                index += 1;
+            },
+            Status::NeedsInput => match session.events.get(index) {
+                Some(&Event::Input(value)) => {
+                    machine.feed(value);
+                    index += 1;
+                },
+                None => return Ok(()),
+                _ => return diverged(index, Actual::NeedsInput),
+            },
+            _ if index == session.events.len() => return Ok(()),
+            _ => return diverged(index, Actual::Halted),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::parse_program;
+
+    // Read numbers and echo them back doubled until a zero is read
+    const DOUBLER: &str = "3,15,1006,15,14,102,2,15,16,4,16,1105,1,0,99,0,0";
+
+    fn doubler() -> Machine<VecDeque<i64>, Vec<i64>, SessionRecorder> {
+        Machine::new(parse_program(DOUBLER).unwrap()).with_tracer(SessionRecorder::new())
+    }
+
+    #[test]
+    fn test_record() {
+        let mut machine = doubler();
+
+        for value in &[3, -4, 0] {
+            machine.feed(*value);
+        }
+
+        assert_eq!(machine.run_until_blocked(), Ok(Status::Halted));
+
+        let session = machine.tracer().session().clone();
+        let text = session.to_string();
+
+        assert_eq!(text, "intcode-session 1\nin 3\nout 6\nin -4\nout -8\nin 0\n");
+        assert_eq!(Session::parse(&text), Ok(session.clone()));
+        assert_eq!(replay(&mut doubler(), &session), Ok(()));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let session = Session::parse("intcode-session 1\nin 3\nout 7\n").unwrap();
+
+        assert_eq!(
+            replay(&mut doubler(), &session),
+            Err(ReplayError::Diverged {index: 1, expected: Some(Event::Output(7)), actual: Actual::Output(6)}),
+        );
+
+        let session = Session::parse("intcode-session 1\nin 0\nout 0\n").unwrap();
+        let error = replay(&mut doubler(), &session).unwrap_err();
+
+        assert_eq!(error.to_string(), "diverged at event 2: expected output 0, but the program halted");
+        assert_eq!(Session::parse("intcode-session 1\nin x\n").unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::machine::Status;
+use super::memory::DEFAULT_LIMIT;
+use super::opcode::{format_program, parse_program};
+
+// Bumped whenever the format changes in a way older readers can't handle
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const MAGIC: &str = "intcode-snapshot";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl SnapshotError {
+    fn new(line: usize, message: String) -> SnapshotError {
+        SnapshotError {line, message}
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Everything needed to carry on running a machine later, apart from its tracer,
+// watchpoints and history. Only Halted and NeedsInput are kept as statuses, since a
+// machine resumes from any other status the same way
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub memory: Vec<i64>,
+    pub sparse: Vec<(usize, i64)>,
+    pub memory_limit: usize,
+    pub ip: usize,
+    pub relative_base: i64,
+    pub status: Status,
+    pub cycles: u64,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Halted => "halted",
+        Status::NeedsInput => "needs-input",
+        _ => "running",
+    }
+}
+
+// An empty list is written as a bare key, which format_program can't round trip
+fn parse_list(text: &str) -> Result<Vec<i64>, String> {
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+
+    parse_program(text).map_err(|e| e.to_string())
+}
+
+fn parse_sparse(text: &str) -> Result<Vec<(usize, i64)>, String> {
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+
+    text.split(',').map(|cell| {
+        let idx = cell.find('=').ok_or_else(|| format!("'{}' should be ADDR=VALUE", cell))?;
+        let address = usize::from_str(cell[..idx].trim()).map_err(|e| e.to_string())?;
+        let value = i64::from_str(cell[idx + 1..].trim()).map_err(|e| e.to_string())?;
+
+        Ok((address, value))
+    }).collect()
+}
+
+fn parse_status(text: &str) -> Result<Status, String> {
+    match text {
+        "running" => Ok(Status::Running),
+        "halted" => Ok(Status::Halted),
+        "needs-input" => Ok(Status::NeedsInput),
+        _ => Err(format!("unknown status '{}'", text)),
+    }
+}
+
+fn parse_value<T: FromStr>(text: &str) -> Result<T, String> where T::Err: fmt::Display {
+    T::from_str(text).map_err(|e| format!("'{}': {}", text, e))
+}
+
+impl Snapshot {
+    // The state of a machine about to run a program from the start
+    pub fn new(program: Vec<i64>) -> Snapshot {
+        Snapshot {
+            memory: program,
+            sparse: vec![],
+            memory_limit: DEFAULT_LIMIT,
+            ip: 0,
+            relative_base: 0,
+            status: Status::Running,
+            cycles: 0,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.trim()));
+
+        let version = match lines.next() {
+            Some((_, header)) if header.starts_with(MAGIC) => header[MAGIC.len()..].trim(),
+            _ => return Err(SnapshotError::new(1, "not an Intcode snapshot".to_string())),
+        };
+
+        match u32::from_str(version) {
+            Ok(SNAPSHOT_VERSION) => (),
+            _ => return Err(SnapshotError::new(1, format!("unsupported snapshot version '{}'", version))),
+        }
+
+        let mut snapshot = Snapshot::new(vec![]);
+
+        let mut seen = vec![];
+        let mut sparse_line = 0;
+
+        for (line, text) in lines {
+            if text.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match text.find(' ') {
+                Some(idx) => (&text[..idx], text[idx + 1..].trim()),
+                None => (text, ""),
+            };
+
+            let result = match key {
+                "ip" => parse_value(value).map(|ip| snapshot.ip = ip),
+                "relative-base" => parse_value(value).map(|base| snapshot.relative_base = base),
+                "cycles" => parse_value(value).map(|cycles| snapshot.cycles = cycles),
+                "memory-limit" => parse_value(value).map(|limit| snapshot.memory_limit = limit),
+                "status" => parse_status(value).map(|status| snapshot.status = status),
+                "input" => parse_list(value).map(|values| snapshot.input = values),
+                "output" => parse_list(value).map(|values| snapshot.output = values),
+                "memory" => parse_list(value).map(|values| snapshot.memory = values),
+                "sparse" => parse_sparse(value).map(|cells| { snapshot.sparse = cells; sparse_line = line; }),
+                _ => Err(format!("unknown field '{}'", key)),
+            };
+
+            result.map_err(|message| SnapshotError::new(line, message))?;
+            seen.push(key);
+        }
+
+        // Missing fields are reported against the end of the file
+        for key in &["ip", "relative-base", "status", "memory-limit", "memory"] {
+            if !seen.contains(key) {
+                return Err(SnapshotError::new(text.lines().count(), format!("missing field '{}'", key)));
+            }
+        }
+
+        // The limit may come after the sparse cells, so they're checked once both are in
+        if let Some((address, _)) = snapshot.sparse.iter().find(|(address, _)| *address >= snapshot.memory_limit) {
+            return Err(SnapshotError::new(sparse_line, format!("sparse address {} is beyond the memory limit of {}", address, snapshot.memory_limit)));
+        }
+
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        let text = fs::read_to_string(path)?;
+
+        Snapshot::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter, key: &str, values: &str) -> fmt::Result {
+    if values.is_empty() {
+        writeln!(f, "{}", key)
+    } else {
+        writeln!(f, "{} {}", key, values)
+    }
+}
+
+// One "key value" line per field, with the memory as a comma-separated program so a
+// snapshot of a fresh machine is easy to eyeball against the original
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sparse: Vec<String> = self.sparse.iter().map(|(address, value)| format!("{}={}", address, value)).collect();
+
+        writeln!(f, "{} {}", MAGIC, SNAPSHOT_VERSION)?;
+        writeln!(f, "ip {}", self.ip)?;
+        writeln!(f, "relative-base {}", self.relative_base)?;
+        writeln!(f, "status {}", status_name(self.status))?;
+        writeln!(f, "cycles {}", self.cycles)?;
+        writeln!(f, "memory-limit {}", self.memory_limit)?;
+        write_list(f, "input", &format_program(&self.input))?;
+        write_list(f, "output", &format_program(&self.output))?;
+        write_list(f, "memory", &format_program(&self.memory))?;
+        write_list(f, "sparse", &sparse.join(","))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    #[test]
+    fn test_round_trip() {
+        let mut machine = Machine::new(parse_program("3,20,109,3,21101,1,2,0,4,3,3,21,99").unwrap());
+        machine.memory_mut().set(1 << 24, -9).unwrap();
+        machine.feed(6);
+        machine.feed(7);
+
+        assert_eq!(machine.run(), Ok(Status::Output(3)));
+
+        let snapshot = machine.snapshot();
+        let text = snapshot.to_string();
+
+        assert!(text.starts_with("intcode-snapshot 1\nip 10\nrelative-base 3\nstatus running\ncycles 4\n"));
+        assert!(text.contains("\ninput 7\noutput 3\n"));
+        assert!(text.ends_with("\nsparse 16777216=-9\n"));
+        assert_eq!(Snapshot::parse(&text), Ok(snapshot.clone()));
+
+        // A fork carries on exactly as the original does
+        let mut fork = Machine::from_snapshot(&snapshot);
+
+        assert_eq!(fork.run(), Ok(Status::Halted));
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(fork.snapshot(), machine.snapshot());
+        assert_eq!(fork.memory().get(21), Ok(7));
+        assert_eq!(fork.output(), &vec![3]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Snapshot::parse("1,2,3").unwrap_err().message, "not an Intcode snapshot");
+        assert_eq!(Snapshot::parse("intcode-snapshot 2\n").unwrap_err().message, "unsupported snapshot version '2'");
+        assert_eq!(Snapshot::parse("intcode-snapshot 1\nip x\n").unwrap_err().line, 2);
+        assert_eq!(Snapshot::parse("intcode-snapshot 1\nip 0\n").unwrap_err().message, "missing field 'relative-base'");
+
+        let mut snapshot = Snapshot::new(vec![99]);
+        snapshot.memory_limit = 100;
+        snapshot.sparse = vec![(100, 7)];
+        let error = Snapshot::parse(&snapshot.to_string()).unwrap_err();
+
+        assert_eq!(error.line, snapshot.to_string().lines().count());
+        assert_eq!(error.message, "sparse address 100 is beyond the memory limit of 100");
+    }
+}
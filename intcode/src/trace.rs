@@ -0,0 +1,279 @@
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::IntcodeError;
+use super::opcode::{mnemonic, OpcodeMode};
+
+// One executed instruction. Operands are the resolved values the instruction read, and
+// write is the address and value it stored, if any
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent<'a> {
+    pub ip: usize,
+    pub instruction: i64,
+    pub opcode: i64,
+    pub modes: &'a [OpcodeMode],
+    pub operands: &'a [i64],
+    pub write: Option<(usize, i64)>,
+    pub relative_base: i64,
+}
+
+pub trait Tracer {
+    // Whether events are wanted at all, so the machine can skip building them
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn trace(&mut self, event: &TraceEvent);
+
+    fn fail(&mut self, _error: &IntcodeError) {}
+}
+
+// Trace nothing
+impl Tracer for () {
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn trace(&mut self, _event: &TraceEvent) {}
+}
+
+// Lets one tracer follow several machines in turn
+impl<T: Tracer> Tracer for &mut T {
+    fn enabled(&self) -> bool {
+        (**self).enabled()
+    }
+
+    fn trace(&mut self, event: &TraceEvent) {
+        (**self).trace(event);
+    }
+
+    fn fail(&mut self, error: &IntcodeError) {
+        (**self).fail(error);
+    }
+}
+
+// An optional tracer, which traces nothing when missing
+impl<T: Tracer> Tracer for Option<T> {
+    fn enabled(&self) -> bool {
+        self.as_ref().is_some_and(|tracer| tracer.enabled())
+    }
+
+    fn trace(&mut self, event: &TraceEvent) {
+        if let Some(tracer) = self {
+            tracer.trace(event);
+        }
+    }
+
+    fn fail(&mut self, error: &IntcodeError) {
+        if let Some(tracer) = self {
+            tracer.fail(error);
+        }
+    }
+}
+
+// Two tracers watching the same machine, e.g. a trace file and a profiler
+impl<A: Tracer, B: Tracer> Tracer for (A, B) {
+    fn enabled(&self) -> bool {
+        self.0.enabled() || self.1.enabled()
+    }
+
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.0.enabled() {
+            self.0.trace(event);
+        }
+
+        if self.1.enabled() {
+            self.1.trace(event);
+        }
+    }
+
+    fn fail(&mut self, error: &IntcodeError) {
+        self.0.fail(error);
+        self.1.fail(error);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TraceFormat {
+    Off,
+    Text,
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<TraceFormat, String> {
+        match text {
+            "off" => Ok(TraceFormat::Off),
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("'{}' should be off, text or json", text)),
+        }
+    }
+}
+
+// The one-line mnemonic form, e.g. "4:\tADD   1, 2\t-> 7"
+pub fn format_text(event: &TraceEvent) -> String {
+    let name = mnemonic(event.opcode).unwrap_or("???");
+    let operands: Vec<String> = event.operands.iter().map(|value| value.to_string()).collect();
+
+    match (operands.is_empty(), event.write) {
+        (true, None) => format!("{}:\t{}", event.ip, name),
+        (true, Some((address, _))) => format!("{}:\t{:<6}{}", event.ip, name, address),
+        (false, None) => format!("{}:\t{:<6}{}", event.ip, name, operands.join(", ")),
+        (false, Some((address, _))) => format!("{}:\t{:<6}{}\t-> {}", event.ip, name, operands.join(", "), address),
+    }
+}
+
+fn json_list<T, F: Fn(&T) -> String>(values: &[T], f: F) -> String {
+    format!("[{}]", values.iter().map(f).collect::<Vec<String>>().join(","))
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(result, "\\u{:04x}", c as u32); },
+            c => result.push(c),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+// A single JSON object, so a trace is one object per line
+pub fn format_json(event: &TraceEvent) -> String {
+    let modes = json_list(event.modes, |mode| match mode {
+        OpcodeMode::Position => "\"position\"".to_string(),
+        OpcodeMode::Immediate => "\"immediate\"".to_string(),
+        OpcodeMode::Relative => "\"relative\"".to_string(),
+    });
+
+    let write = match event.write {
+        Some((address, value)) => format!("{{\"address\":{},\"value\":{}}}", address, value),
+        None => "null".to_string(),
+    };
+
+    format!(
+        "{{\"ip\":{},\"instruction\":{},\"opcode\":{},\"mnemonic\":{},\"modes\":{},\"operands\":{},\"write\":{},\"relative_base\":{}}}",
+        event.ip,
+        event.instruction,
+        event.opcode,
+        json_string(mnemonic(event.opcode).unwrap_or("???")),
+        modes,
+        json_list(event.operands, |value| value.to_string()),
+        write,
+        event.relative_base,
+    )
+}
+
+// Writes each event as a line of text or JSON. Trace output is best effort, so write
+// errors are ignored rather than stopping the program
+#[derive(Debug)]
+pub struct TraceWriter<W: io::Write> {
+    format: TraceFormat,
+    writer: W,
+}
+
+impl<W: io::Write> TraceWriter<W> {
+    pub fn new(format: TraceFormat, writer: W) -> TraceWriter<W> {
+        TraceWriter {format, writer}
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+}
+
+impl<W: io::Write> Tracer for TraceWriter<W> {
+    fn enabled(&self) -> bool {
+        self.format != TraceFormat::Off
+    }
+
+    fn trace(&mut self, event: &TraceEvent) {
+        let line = match self.format {
+            TraceFormat::Off => return,
+            TraceFormat::Text => format_text(event),
+            TraceFormat::Json => format_json(event),
+        };
+
+        let _ = writeln!(self.writer, "{}", line);
+    }
+
+    fn fail(&mut self, error: &IntcodeError) {
+        let _ = match self.format {
+            TraceFormat::Off => return,
+            TraceFormat::Text => writeln!(self.writer, "{}:\tERR   {}", error.ip(), error),
+            TraceFormat::Json => writeln!(
+                self.writer,
+                "{{\"ip\":{},\"instruction\":{},\"error\":{}}}",
+                error.ip(), error.instruction(), json_string(&error.to_string()),
+            ),
+        };
+    }
+}
+
+// The tracer behind the binaries' --trace and --trace-file options, which writes to
+// stderr unless given a file
+pub fn open_tracer(format: TraceFormat, path: Option<&Path>) -> io::Result<TraceWriter<Box<dyn io::Write>>> {
+    let writer: Box<dyn io::Write> = match path {
+        Some(path) if format != TraceFormat::Off => Box::new(io::BufWriter::new(File::create(path)?)),
+        _ => Box::new(io::stderr()),
+    };
+
+    Ok(TraceWriter::new(format, writer))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [OpcodeMode; 3] = [OpcodeMode::Immediate, OpcodeMode::Relative, OpcodeMode::Position];
+
+    fn add() -> TraceEvent<'static> {
+        TraceEvent {ip: 4, instruction: 1201, opcode: 1, modes: &MODES, operands: &[2, -3], write: Some((7, -1)), relative_base: 5}
+    }
+
+    #[test]
+    fn test_text() {
+        let input = TraceEvent {ip: 0, instruction: 3, opcode: 3, modes: &MODES[2..], operands: &[], write: Some((9, 1)), relative_base: 0};
+        let halt = TraceEvent {ip: 2, instruction: 99, opcode: 99, modes: &[], operands: &[], write: None, relative_base: 0};
+
+        assert_eq!(format_text(&add()), "4:\tADD   2, -3\t-> 7");
+        assert_eq!(format_text(&input), "0:\tIN    9");
+        assert_eq!(format_text(&halt), "2:\tHALT");
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            format_json(&add()),
+            "{\"ip\":4,\"instruction\":1201,\"opcode\":1,\"mnemonic\":\"ADD\",\"modes\":[\"immediate\",\"relative\",\"position\"],\
+             \"operands\":[2,-3],\"write\":{\"address\":7,\"value\":-1},\"relative_base\":5}",
+        );
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut tracer = TraceWriter::new(TraceFormat::Off, vec![]);
+        tracer.trace(&add());
+
+        assert!(!tracer.enabled());
+        assert!(tracer.writer().is_empty());
+
+        let mut tracer = TraceWriter::new(TraceFormat::Text, vec![]);
+        tracer.trace(&add());
+        tracer.fail(&IntcodeError::UnknownOpcode {ip: 8, instruction: 42});
+
+        assert_eq!(String::from_utf8(tracer.writer().clone()).unwrap(), "4:\tADD   2, -3\t-> 7\n8:\tERR   ip 8 (42): unknown opcode 42\n");
+    }
+}
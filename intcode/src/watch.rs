@@ -0,0 +1,110 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// A range of addresses to keep an eye on, excluding the end address
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    pub fn new(start: usize, end: usize) -> Watchpoint {
+        Watchpoint {start, end, on_read: true, on_write: true}
+    }
+
+    pub fn matches(&self, address: usize, access: Access) -> bool {
+        let wanted = match access {
+            Access::Read => self.on_read,
+            Access::Write => self.on_write,
+        };
+
+        wanted && address >= self.start && address < self.end
+    }
+}
+
+// Parses ADDR or ADDR..END, optionally followed by :r, :w or :rw
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Watchpoint, String> {
+        let (range, kind) = match text.find(':') {
+            Some(idx) => (&text[..idx], &text[idx + 1..]),
+            None => (text, "rw"),
+        };
+
+        let parse = |value: &str| usize::from_str(value.trim()).map_err(|_| format!("'{}' is not a valid address", value));
+
+        let (start, end) = match range.find("..") {
+            Some(idx) => (parse(&range[..idx])?, parse(&range[idx + 2..])?),
+            None => {
+                let address = parse(range)?;
+                (address, address.checked_add(1).ok_or_else(|| format!("'{}' is not a valid address", range))?)
+            },
+        };
+
+        if end <= start {
+            return Err(format!("'{}' is an empty range", range));
+        }
+
+        let (on_read, on_write) = match kind {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" => (true, true),
+            _ => return Err(format!("'{}' should be r, w or rw", kind)),
+        };
+
+        Ok(Watchpoint {start, end, on_read, on_write})
+    }
+}
+
+// A watched address being touched. For reads old and new are both the value read
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WatchEvent {
+    pub ip: usize,
+    pub address: usize,
+    pub access: Access,
+    pub old: i64,
+    pub new: i64,
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "{}:\tread  [{}] = {}", self.ip, self.address, self.old),
+            Access::Write => write!(f, "{}:\twrite [{}] {} -> {}", self.ip, self.address, self.old, self.new),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Watchpoint::from_str("12"), Ok(Watchpoint::new(12, 13)));
+        assert_eq!(Watchpoint::from_str("10..20:w"), Ok(Watchpoint {start: 10, end: 20, on_read: false, on_write: true}));
+        assert!(Watchpoint::from_str("10..10").is_err());
+        assert!(Watchpoint::from_str("10:x").is_err());
+        assert_eq!(Watchpoint::from_str("18446744073709551615"), Err("'18446744073709551615' is not a valid address".to_string()));
+    }
+
+    #[test]
+    fn test_matches() {
+        let watchpoint = Watchpoint::from_str("10..20:r").unwrap();
+
+        assert!(watchpoint.matches(10, Access::Read));
+        assert!(!watchpoint.matches(20, Access::Read));
+        assert!(!watchpoint.matches(15, Access::Write));
+    }
+}
@@ -0,0 +1,118 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::io::{Input, Output};
+use super::machine::Machine;
+use super::memory::Memory;
+use super::trace::Tracer;
+
+// The guards against runaway programs, bundled up so they're easy to apply to each
+// machine in a batch
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub detect_loops: bool,
+}
+
+impl Limits {
+    // The timeout starts counting from here
+    pub fn apply<I: Input, O: Output, T: Tracer>(&self, machine: &mut Machine<I, O, T>) {
+        machine.set_step_limit(self.steps);
+        machine.set_timeout(self.timeout);
+        machine.set_loop_detection(self.detect_loops);
+    }
+}
+
+// Parse a number of seconds, which may be fractional, for timeout options
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    match f64::from_str(text) {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("'{}' is not a valid number of seconds", text)),
+    }
+}
+
+// Spots a machine going round in circles without doing any I/O. Between inputs and
+// outputs a machine is deterministic, so if its whole state ever repeats it will repeat
+// forever. Uses Brent's algorithm: the state is saved at doubling intervals and each
+// step is compared against the last saved one, which finds any cycle without keeping
+// more than one copy of memory
+#[derive(Clone, Debug, Default)]
+pub struct LoopDetector {
+    saved: Option<(usize, i64, Memory)>,
+    // Whether anything was written since the state was saved, as if not the memory is
+    // known to match without comparing it
+    written: bool,
+    power: u64,
+    length: u64,
+}
+
+impl LoopDetector {
+    pub fn new() -> LoopDetector {
+        LoopDetector::default()
+    }
+
+    // Forget everything, e.g. after I/O, since the machine is in a new situation
+    pub fn reset(&mut self) {
+        *self = LoopDetector::default();
+    }
+
+    pub fn note_write(&mut self) {
+        self.written = true;
+    }
+
+    // Check the state after a step, returning true if it's been seen before
+    pub fn check(&mut self, ip: usize, relative_base: i64, memory: &Memory) -> bool {
+        if let Some((saved_ip, saved_base, saved_memory)) = &self.saved {
+            if *saved_ip == ip && *saved_base == relative_base && (!self.written || saved_memory == memory) {
+                return true;
+            }
+        }
+
+        self.length += 1;
+
+        if self.length >= self.power {
+            self.saved = Some((ip, relative_base, memory.clone()));
+            self.written = false;
+            self.power = (self.power * 2).max(1);
+            self.length = 0;
+        }
+
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_seconds("1.5"), Ok(Duration::from_millis(1500)));
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("soon").is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let memory = Memory::new(vec![0; 4]);
+        let mut detector = LoopDetector::new();
+
+        // Three states going round and round
+        let found = (0..20).position(|step| detector.check(step % 3, 0, &memory));
+
+        assert!(found.is_some());
+
+        // A counter in memory means the state never repeats
+        let mut memory = Memory::new(vec![0; 4]);
+        detector.reset();
+
+        for step in 0..1000 {
+            memory.set(3, step as i64).unwrap();
+            detector.note_write();
+
+            assert!(!detector.check(step % 3, 0, &memory));
+        }
+    }
+}